@@ -0,0 +1,308 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use lapin::types::FieldTable;
+
+use crate::{
+    completion::{CompletionReason, CompletionStrategy, Progress},
+    function::{AggregationError, AggregationFunction, Part},
+    groups::PerGroup,
+    window::TimeWindow,
+};
+
+/// A consumed message, as far as the aggregator is concerned.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub correlation_key: String,
+    /// The aggregation group the message belongs to, which selects its [`CompletionStrategy`] and
+    /// [`AggregationFunction`].
+    pub group: Option<String>,
+    pub headers: Option<FieldTable>,
+    pub payload: String,
+    /// Delivery tag of the message, which stays unacknowledged until the aggregate it ends up in is published.
+    pub delivery_tag: u64,
+}
+
+/// Payloads of a correlation key that were aggregated together by the [`AggregationFunction`] of the buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub correlation_key: String,
+    pub group: Option<String>,
+    /// How many messages were aggregated.
+    pub received: usize,
+    pub payload: String,
+    /// Content type of the payload, if the aggregation function determines it.
+    pub content_type: Option<String>,
+    pub reason: CompletionReason,
+    /// Delivery tags of the aggregated messages.
+    pub delivery_tags: Vec<u64>,
+    /// The time window of the messages, in the windowed mode.
+    pub window: Option<TimeWindow>,
+}
+
+/// The messages collected so far for a single correlation key.
+#[derive(Debug)]
+struct Buffer {
+    group: Option<String>,
+    strategy: CompletionStrategy,
+    function: Arc<dyn AggregationFunction>,
+    parts: Vec<Part>,
+    delivery_tags: Vec<u64>,
+    expected: Option<u64>,
+    first_at: Instant,
+    last_at: Instant,
+}
+
+impl Buffer {
+    fn progress(&self) -> Progress {
+        Progress {
+            received: self.parts.len(),
+            expected: self.expected,
+            first_at: self.first_at,
+            last_at: self.last_at,
+        }
+    }
+}
+
+/// Collects payloads into a separate buffer per correlation key, so that messages of concurrent conversations
+/// are never aggregated together, until the [`CompletionStrategy`] of the buffer says it is complete.
+#[derive(Debug)]
+pub struct Aggregator {
+    strategies: PerGroup<CompletionStrategy>,
+    functions: PerGroup<Arc<dyn AggregationFunction>>,
+    buffers: HashMap<String, Buffer>,
+}
+
+impl Aggregator {
+    pub fn new(
+        strategies: PerGroup<CompletionStrategy>,
+        functions: PerGroup<Arc<dyn AggregationFunction>>,
+    ) -> Aggregator {
+        Aggregator {
+            strategies,
+            functions,
+            buffers: HashMap::new(),
+        }
+    }
+
+    /// Adds the payload to the buffer of its correlation key, and returns the aggregate once it is complete.
+    ///
+    /// The strategy and function of a buffer are those of the group of its first message. A message the function
+    /// cannot aggregate is refused, leaving the buffer as it was.
+    pub fn push(
+        &mut self,
+        message: Message,
+        now: Instant,
+    ) -> Result<Option<Aggregate>, AggregationError> {
+        let part = Part {
+            payload: message.payload,
+            headers: message.headers,
+        };
+        match self.buffers.get(&message.correlation_key) {
+            Some(buffer) => buffer.function.check(&part)?,
+            None => self
+                .functions
+                .for_group(message.group.as_deref())
+                .check(&part)?,
+        }
+
+        let (strategies, functions) = (&self.strategies, &self.functions);
+        let buffer = self
+            .buffers
+            .entry(message.correlation_key.clone())
+            .or_insert_with(|| Buffer {
+                strategy: strategies.for_group(message.group.as_deref()).clone(),
+                function: Arc::clone(functions.for_group(message.group.as_deref())),
+                group: message.group.clone(),
+                parts: Vec::new(),
+                delivery_tags: Vec::new(),
+                expected: None,
+                first_at: now,
+                last_at: now,
+            });
+
+        if let Some(expected) = buffer.strategy.expected_count(part.headers.as_ref()) {
+            buffer.expected = Some(expected);
+        }
+        buffer.parts.push(part);
+        buffer.delivery_tags.push(message.delivery_tag);
+        buffer.last_at = now;
+
+        let payload = buffer
+            .parts
+            .last()
+            .map(|part| part.payload.as_str())
+            .unwrap_or_default();
+        match buffer.strategy.on_message(&buffer.progress(), payload) {
+            Some(reason) => Ok(self.complete(message.correlation_key, reason)),
+            None => Ok(None),
+        }
+    }
+
+    /// Completes every buffer whose strategy says it is complete by the time `now`, such as after a timeout.
+    pub fn expire(&mut self, now: Instant) -> Vec<Aggregate> {
+        let expired: Vec<(String, CompletionReason)> = self
+            .buffers
+            .iter()
+            .filter_map(|(correlation_key, buffer)| {
+                let reason = buffer.strategy.on_tick(&buffer.progress(), now)?;
+                Some((correlation_key.clone(), reason))
+            })
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|(correlation_key, reason)| self.complete(correlation_key, reason))
+            .collect()
+    }
+
+    /// How many correlation keys have a buffer that is not complete yet.
+    pub fn open_buffers(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Clears the buffer of the correlation key, turning it into an aggregate.
+    fn complete(&mut self, correlation_key: String, reason: CompletionReason) -> Option<Aggregate> {
+        let buffer = self.buffers.remove(&correlation_key)?;
+        Some(Aggregate {
+            correlation_key,
+            group: buffer.group,
+            received: buffer.parts.len(),
+            payload: buffer.function.aggregate(&buffer.parts),
+            content_type: buffer.function.content_type().map(String::from),
+            reason,
+            delivery_tags: buffer.delivery_tags,
+            window: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        function::{self, Concat},
+        groups::parse_per_group,
+    };
+
+    fn aggregator() -> Aggregator {
+        Aggregator::new(
+            PerGroup {
+                default: "count:2".parse().unwrap(),
+                groups: parse_per_group("batch=contains:EOF|inactivity:5s", str::parse).unwrap(),
+            },
+            PerGroup {
+                default: Arc::new(Concat),
+                groups: parse_per_group("totals=sum:/amount", function::from_name).unwrap(),
+            },
+        )
+    }
+
+    fn message(correlation_key: &str, group: Option<&str>, payload: &str) -> Message {
+        Message {
+            correlation_key: correlation_key.to_string(),
+            group: group.map(String::from),
+            headers: None,
+            payload: payload.to_string(),
+            delivery_tag: 0,
+        }
+    }
+
+    #[test]
+    fn interleaved_conversations_are_aggregated_apart() {
+        let mut aggregator = aggregator();
+        let now = Instant::now();
+
+        assert_eq!(aggregator.push(message("a", None, "a1"), now), Ok(None));
+        assert_eq!(aggregator.push(message("b", None, "b1"), now), Ok(None));
+        assert_eq!(aggregator.open_buffers(), 2);
+
+        let a = aggregator
+            .push(message("a", None, "a2"), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(a.correlation_key, "a");
+        assert_eq!(a.payload, "a1a2");
+        assert_eq!(a.reason, CompletionReason::Count);
+
+        let b = aggregator
+            .push(message("b", None, "b2"), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(b.payload, "b1b2");
+        assert_eq!(aggregator.open_buffers(), 0);
+    }
+
+    #[test]
+    fn the_group_of_the_first_message_selects_the_strategy() {
+        let mut aggregator = aggregator();
+        let now = Instant::now();
+
+        aggregator
+            .push(message("a", Some("batch"), "1"), now)
+            .unwrap();
+        assert_eq!(aggregator.push(message("a", None, "2"), now), Ok(None));
+        let a = aggregator
+            .push(message("a", None, "3 EOF"), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(a.received, 3);
+        assert_eq!(a.group.as_deref(), Some("batch"));
+        assert_eq!(a.reason, CompletionReason::Predicate);
+    }
+
+    #[test]
+    fn the_group_of_the_first_message_selects_the_function() {
+        let mut aggregator = aggregator();
+        let now = Instant::now();
+
+        aggregator
+            .push(message("a", Some("totals"), r#"{"amount": 3}"#), now)
+            .unwrap();
+        let refused = aggregator.push(message("a", None, "no amount"), now);
+        assert_eq!(refused.unwrap_err().reason(), "invalid_json");
+
+        let a = aggregator
+            .push(message("a", None, r#"{"amount": 4}"#), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(a.received, 2);
+        assert_eq!(a.payload, "7");
+        assert_eq!(a.content_type.as_deref(), Some("application/json"));
+    }
+
+    #[test]
+    fn aggregates_carry_the_delivery_tags_of_their_messages() {
+        let mut aggregator = aggregator();
+        let now = Instant::now();
+        let tagged = |delivery_tag, payload| Message {
+            delivery_tag,
+            ..message("a", Some("totals"), payload)
+        };
+
+        aggregator.push(tagged(1, r#"{"amount": 1}"#), now).unwrap();
+        assert!(aggregator.push(tagged(2, "refused"), now).is_err());
+        let a = aggregator
+            .push(tagged(3, r#"{"amount": 2}"#), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(a.delivery_tags, vec![1, 3]);
+    }
+
+    #[test]
+    fn buffers_expire_without_another_message() {
+        let mut aggregator = aggregator();
+        let now = Instant::now();
+        aggregator
+            .push(message("a", Some("batch"), "1"), now)
+            .unwrap();
+        aggregator.push(message("b", None, "1"), now).unwrap();
+
+        assert!(aggregator.expire(now + Duration::from_secs(4)).is_empty());
+        let expired = aggregator.expire(now + Duration::from_secs(5));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].correlation_key, "a");
+        assert_eq!(expired[0].reason, CompletionReason::Inactivity);
+        assert_eq!(aggregator.open_buffers(), 1);
+    }
+}
@@ -0,0 +1,93 @@
+//! RabbitMQ plumbing shared by `aggregate-receiver` and `scatter-gather`, which both publish what they combined from
+//! several deliveries and settle those deliveries by the outcome of that publish.
+
+use lapin::{
+    options::{BasicAckOptions, BasicNackOptions, ExchangeDeclareOptions},
+    publisher_confirm::PublisherConfirm,
+    types::FieldTable,
+    Channel, ExchangeKind,
+};
+
+/// Declares the exchange aggregates are published to as a durable topic exchange, so that consumers can bind by
+/// routing key. An empty name is the default exchange, which needs no declaring.
+pub async fn declare_exchange(channel: &Channel, exchange: &str) {
+    if exchange.is_empty() {
+        return;
+    }
+
+    if let Err(e) = channel
+        .exchange_declare(
+            exchange,
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..ExchangeDeclareOptions::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+    {
+        panic!(
+            "[Critical] Could not declare exchange '{}': {}",
+            exchange, e
+        );
+    }
+}
+
+/// Waits for the broker to confirm a publish of `what`, such as an aggregate or a gathered response.
+/// Returns `false` if the publish failed or the broker rejected it.
+pub async fn confirmed(published: Result<PublisherConfirm, lapin::Error>, what: &str) -> bool {
+    match published {
+        Ok(confirm) => match confirm.await {
+            Ok(confirmation) => confirmation.is_ack(),
+            Err(e) => {
+                println!("[Error] Could not confirm {}: {}", what, e);
+                false
+            }
+        },
+        Err(e) => {
+            println!("[Error] Could not publish {}: {}", what, e);
+            false
+        }
+    }
+}
+
+/// Acknowledges the deliveries something confirmed was combined from. Otherwise requeues them, so they are
+/// collected again and take part in a later publish rather than being dropped.
+pub async fn settle(channel: &Channel, confirmed: bool, delivery_tags: &[u64]) {
+    for &delivery_tag in delivery_tags {
+        let result = if confirmed {
+            channel
+                .basic_ack(delivery_tag, BasicAckOptions::default())
+                .await
+        } else {
+            channel
+                .basic_nack(
+                    delivery_tag,
+                    BasicNackOptions {
+                        requeue: true,
+                        ..BasicNackOptions::default()
+                    },
+                )
+                .await
+        };
+        if let Err(e) = result {
+            println!(
+                "[Error] Failed to settle delivery tag '{}': {}",
+                delivery_tag, e
+            );
+        }
+    }
+
+    if delivery_tags.is_empty() {
+        return;
+    }
+    if confirmed {
+        println!(
+            "[Information] {} message(s) acknowledged...",
+            delivery_tags.len()
+        );
+    } else {
+        println!("[Warning] {} message(s) requeued...", delivery_tags.len());
+    }
+}
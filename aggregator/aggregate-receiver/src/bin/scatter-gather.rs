@@ -0,0 +1,295 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use aggregate_receiver::{
+    amqp,
+    config::ScatterGatherConfig,
+    scatter::{self, Gathered, Gatherer, Request},
+};
+use lapin::{
+    message::DeliveryResult,
+    options::{
+        BasicConsumeOptions, BasicNackOptions, BasicPublishOptions, ConfirmSelectOptions,
+        QueueDeclareOptions,
+    },
+    types::FieldTable,
+    BasicProperties, Channel, Connection, ConnectionProperties,
+};
+use tokio::sync::Mutex;
+
+/// How often the scatters are checked for their deadline, independently of incoming replies.
+const DEADLINE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[tokio::main]
+async fn main() {
+    let config = match ScatterGatherConfig::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => panic!("[Critical] {}", e),
+    };
+    println!("[Information] Configuration: {:?}", config);
+
+    // An atomic reference counter to an asynchronous mutual exclusion wrapper.
+    // For the sake of being able to have multiple threads safely access and mutate the wrapped data.
+    let gatherer = Arc::new(Mutex::new(Gatherer::new()));
+
+    let options = ConnectionProperties::default()
+        .with_connection_name("scatter_gather_connection".to_string().into())
+        .with_executor(tokio_executor_trait::Tokio::current())
+        .with_reactor(tokio_reactor_trait::Tokio);
+
+    let connection = match Connection::connect(config.broker.as_str(), options.clone()).await {
+        Ok(con) => con,
+        Err(e) => panic!(
+            "[Critical] Could not establish connection to RabbitMQ: {}",
+            e
+        ),
+    };
+
+    let channel = match connection.create_channel().await {
+        Ok(ch) => ch,
+        Err(e) => panic!("[Critical] Could not create channel: {}", e),
+    };
+
+    // Every combined response is confirmed by the broker before the request it answers is acknowledged.
+    channel
+        .confirm_select(ConfirmSelectOptions::default())
+        .await
+        .unwrap();
+
+    let _queue = channel
+        .queue_declare(
+            &config.queue,
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .unwrap();
+
+    // The replies of every scatter arrive on a queue of its own, named by the broker
+    let reply_queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .unwrap();
+    let reply_queue = reply_queue.name().as_str().to_string();
+
+    let replies = channel
+        .basic_consume(
+            &reply_queue,
+            &format!("{}-replies", config.consumer_tag),
+            BasicConsumeOptions {
+                no_ack: true,
+                ..BasicConsumeOptions::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .unwrap();
+
+    let requests = channel
+        .basic_consume(
+            &config.queue,
+            &config.consumer_tag,
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .unwrap();
+
+    // Answer the requests whose deadline passed, even when no replies arrive to trigger it.
+    tokio::spawn({
+        let gatherer = Arc::clone(&gatherer);
+        let channel = channel.clone();
+        async move {
+            let mut interval = tokio::time::interval(DEADLINE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let expired = gatherer.lock().await.expire(Instant::now());
+                for gathered in expired {
+                    respond(&channel, gathered).await;
+                }
+            }
+        }
+    });
+
+    replies.set_delegate({
+        let gatherer = Arc::clone(&gatherer);
+        let channel = channel.clone();
+        move |delivery: DeliveryResult| {
+            let gatherer = Arc::clone(&gatherer);
+            let channel = channel.clone();
+            async move {
+                let delivery = match delivery {
+                    Ok(Some(delivery)) => delivery,
+                    // The consumer got canceled
+                    Ok(None) => return,
+                    // Carries the error and is always followed by Ok(None)
+                    Err(e) => {
+                        println!("Failed to consume reply: {}", e);
+                        return;
+                    }
+                };
+
+                // Names both the scatter and the recipient the reply is from
+                let correlation_id = match delivery.properties.correlation_id() {
+                    Some(correlation_id) => correlation_id.as_str().to_string(),
+                    None => {
+                        println!("[Warning] Dropping reply without a correlation id");
+                        return;
+                    }
+                };
+                let payload = String::from_utf8_lossy(&delivery.data).into_owned();
+                println!(
+                    "[Information] Received reply to '{}': '{}'",
+                    correlation_id, payload
+                );
+
+                let gathered = gatherer.lock().await.reply(&correlation_id, payload);
+                match gathered {
+                    Ok(Some(gathered)) => respond(&channel, gathered).await,
+                    Ok(None) => {}
+                    Err(e) => println!("[Warning] Dropping reply ({}): {}", e.reason(), e),
+                }
+            }
+        }
+    });
+
+    requests.set_delegate({
+        move |delivery: DeliveryResult| {
+            // Create another pointer to the same allocation.
+            let gatherer = Arc::clone(&gatherer);
+            let channel = channel.clone();
+            let config = Arc::clone(&config);
+            let reply_queue = reply_queue.clone();
+            async move {
+                let delivery = match delivery {
+                    Ok(Some(delivery)) => delivery,
+                    // The consumer got canceled
+                    Ok(None) => return,
+                    // Carries the error and is always followed by Ok(None)
+                    Err(e) => {
+                        println!("Failed to consume request: {}", e);
+                        return;
+                    }
+                };
+
+                let reply_to = match delivery.properties.reply_to() {
+                    Some(reply_to) => reply_to.as_str().to_string(),
+                    None => {
+                        // Without a reply-to there is nowhere to send the combined response
+                        println!("[Warning] Rejecting request without a reply-to");
+                        delivery
+                            .nack(BasicNackOptions::default())
+                            .await
+                            .expect("[Error] Failed to reject request");
+                        return;
+                    }
+                };
+                let request = Request {
+                    reply_to,
+                    correlation_id: delivery
+                        .properties
+                        .correlation_id()
+                        .as_ref()
+                        .map(|correlation_id| correlation_id.as_str().to_string()),
+                    delivery_tag: delivery.delivery_tag,
+                };
+
+                // The scatter is registered before the requests go out, so that no reply can arrive before it
+                let scatter_id = uuid::Uuid::new_v4().to_string();
+                println!(
+                    "[Information] Scattering request {:?} as '{}' to {:?}",
+                    request.correlation_id, scatter_id, config.recipients
+                );
+                gatherer.lock().await.start(
+                    scatter_id.clone(),
+                    request,
+                    config.recipients.clone(),
+                    config.timeout,
+                    Instant::now(),
+                );
+
+                for recipient in &config.recipients {
+                    let mut properties = BasicProperties::default()
+                        .with_correlation_id(scatter::correlation_id(&scatter_id, recipient).into())
+                        .with_reply_to(reply_queue.clone().into());
+                    if let Some(headers) = delivery.properties.headers() {
+                        properties = properties.with_headers(headers.clone());
+                    }
+                    if let Some(content_type) = delivery.properties.content_type() {
+                        properties = properties.with_content_type(content_type.clone());
+                    }
+
+                    // A recipient that cannot be reached is marked as timed out once the deadline passes
+                    let published = channel
+                        .basic_publish(
+                            "",
+                            recipient,
+                            BasicPublishOptions::default(),
+                            &delivery.data,
+                            properties,
+                        )
+                        .await;
+                    let what = format!("request scattered to '{}'", recipient);
+                    if !amqp::confirmed(published, &what).await {
+                        println!(
+                            "[Error] The request scattered to '{}' was not confirmed",
+                            recipient
+                        );
+                    }
+                }
+            }
+        }
+    });
+
+    // Block the main thread
+    std::future::pending::<()>().await;
+}
+
+/// Publishes the combined response to the reply-to of the request, then acknowledges the request.
+///
+/// Should the publish fail or be rejected by the broker, the request is requeued instead, so it is scattered again
+/// rather than lost.
+async fn respond(channel: &Channel, gathered: Gathered) {
+    if gathered.timed_out.is_empty() {
+        println!(
+            "[Information] Gathered every reply to '{}'",
+            gathered.scatter_id
+        );
+    } else {
+        println!(
+            "[Warning] Gathered {} reply(s) to '{}', timed out: {:?}",
+            gathered.replies.len(),
+            gathered.scatter_id,
+            gathered.timed_out
+        );
+    }
+
+    let mut properties = BasicProperties::default()
+        .with_content_type("application/json".into())
+        .with_delivery_mode(2);
+    if let Some(correlation_id) = &gathered.request.correlation_id {
+        properties = properties.with_correlation_id(correlation_id.clone().into());
+    }
+
+    let published = channel
+        .basic_publish(
+            "",
+            &gathered.request.reply_to,
+            BasicPublishOptions::default(),
+            gathered.response().as_bytes(),
+            properties,
+        )
+        .await;
+    let confirmed = amqp::confirmed(published, "response").await;
+    amqp::settle(channel, confirmed, &[gathered.request.delivery_tag]).await;
+}
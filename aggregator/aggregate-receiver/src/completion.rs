@@ -0,0 +1,321 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use lapin::types::{AMQPValue, FieldTable};
+use serde_json::Value;
+
+use crate::config::parse_duration;
+
+/// A condition under which the messages collected for a correlation key are complete.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionCondition {
+    /// A fixed number of messages has arrived.
+    Count(usize),
+    /// As many messages have arrived as the named header of a message says to expect.
+    ExpectedCountHeader(String),
+    /// The time since the first message has passed.
+    Timeout(Duration),
+    /// The time since the latest message has passed without another one arriving.
+    Inactivity(Duration),
+    /// A message has a JSON field, addressed by a JSON pointer, with the given value.
+    JsonFieldEquals { pointer: String, value: Value },
+    /// A message has a payload containing the given text, such as an end-of-batch marker.
+    PayloadContains(String),
+}
+
+/// Which [`CompletionCondition`] completed an aggregate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionReason {
+    Count,
+    ExpectedCount,
+    Timeout,
+    Inactivity,
+    Predicate,
+    /// The time window of the messages closed.
+    WindowClosed,
+    /// A message arrived for a time window that had already closed, within the allowed lateness.
+    LateArrival,
+}
+
+impl fmt::Display for CompletionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            CompletionReason::Count => "count",
+            CompletionReason::ExpectedCount => "expected_count",
+            CompletionReason::Timeout => "timeout",
+            CompletionReason::Inactivity => "inactivity",
+            CompletionReason::Predicate => "predicate",
+            CompletionReason::WindowClosed => "window_closed",
+            CompletionReason::LateArrival => "late_arrival",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl FromStr for CompletionCondition {
+    type Err = String;
+
+    /// Parses one of `count:<n>`, `header:<name>`, `timeout:<duration>`, `inactivity:<duration>`,
+    /// `json:<pointer>=<value>` or `contains:<text>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, argument) = s.trim().split_once(':').ok_or_else(|| {
+            format!(
+                "Invalid completion condition '{}', expected '<kind>:<argument>'",
+                s
+            )
+        })?;
+        if argument.is_empty() {
+            return Err(format!("Completion condition '{}' has no argument", s));
+        }
+
+        match kind.to_ascii_lowercase().as_str() {
+            "count" => match argument.parse::<usize>() {
+                Ok(0) | Err(_) => Err(format!(
+                    "Completion count '{}' has to be a positive number",
+                    argument
+                )),
+                Ok(count) => Ok(CompletionCondition::Count(count)),
+            },
+            "header" => Ok(CompletionCondition::ExpectedCountHeader(
+                argument.to_string(),
+            )),
+            "timeout" => parse_duration(argument).map(CompletionCondition::Timeout),
+            "inactivity" => parse_duration(argument).map(CompletionCondition::Inactivity),
+            "json" => {
+                let (pointer, value) = argument.split_once('=').ok_or_else(|| {
+                    format!(
+                        "Invalid JSON predicate '{}', expected '<pointer>=<value>'",
+                        argument
+                    )
+                })?;
+                let pointer = if pointer.starts_with('/') {
+                    pointer.to_string()
+                } else {
+                    format!("/{}", pointer)
+                };
+                // A value that is not a JSON literal, such as `done`, is taken as a string
+                let value = serde_json::from_str(value)
+                    .unwrap_or_else(|_| Value::String(value.to_string()));
+                Ok(CompletionCondition::JsonFieldEquals { pointer, value })
+            }
+            "contains" => Ok(CompletionCondition::PayloadContains(argument.to_string())),
+            other => Err(format!(
+                "Unknown completion condition '{}', expected 'count', 'header', 'timeout', 'inactivity', 'json' or 'contains'",
+                other
+            )),
+        }
+    }
+}
+
+/// What the completion conditions are evaluated against.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// How many messages have arrived.
+    pub received: usize,
+    /// How many messages are expected, as read from the expected-count header.
+    pub expected: Option<u64>,
+    pub first_at: Instant,
+    pub last_at: Instant,
+}
+
+/// Decides when the messages of a correlation key are complete, which is as soon as any of its conditions holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionStrategy {
+    conditions: Vec<CompletionCondition>,
+}
+
+impl CompletionStrategy {
+    pub fn new(conditions: Vec<CompletionCondition>) -> CompletionStrategy {
+        CompletionStrategy { conditions }
+    }
+
+    /// Reads how many messages are expected from the expected-count header of a message, if the strategy has one.
+    pub fn expected_count(&self, headers: Option<&FieldTable>) -> Option<u64> {
+        self.conditions
+            .iter()
+            .find_map(|condition| match condition {
+                CompletionCondition::ExpectedCountHeader(name) => {
+                    headers.and_then(|headers| count_header(headers.inner().get(name.as_str())?))
+                }
+                _ => None,
+            })
+    }
+
+    /// Whether the messages are complete now that `payload` has arrived, which `progress` already counts.
+    pub fn on_message(&self, progress: &Progress, payload: &str) -> Option<CompletionReason> {
+        self.conditions
+            .iter()
+            .find_map(|condition| match condition {
+                CompletionCondition::Count(count) => {
+                    (progress.received >= *count).then_some(CompletionReason::Count)
+                }
+                CompletionCondition::ExpectedCountHeader(_) => progress
+                    .expected
+                    .filter(|&expected| progress.received as u64 >= expected)
+                    .map(|_| CompletionReason::ExpectedCount),
+                CompletionCondition::JsonFieldEquals { pointer, value } => {
+                    serde_json::from_str::<Value>(payload)
+                        .ok()
+                        .filter(|document| document.pointer(pointer) == Some(value))
+                        .map(|_| CompletionReason::Predicate)
+                }
+                CompletionCondition::PayloadContains(text) => payload
+                    .contains(text.as_str())
+                    .then_some(CompletionReason::Predicate),
+                CompletionCondition::Timeout(_) | CompletionCondition::Inactivity(_) => None,
+            })
+    }
+
+    /// Whether the messages are complete by the time `now`, without another message arriving.
+    pub fn on_tick(&self, progress: &Progress, now: Instant) -> Option<CompletionReason> {
+        self.conditions
+            .iter()
+            .find_map(|condition| match condition {
+                CompletionCondition::Timeout(timeout) => {
+                    (now.saturating_duration_since(progress.first_at) >= *timeout)
+                        .then_some(CompletionReason::Timeout)
+                }
+                CompletionCondition::Inactivity(inactivity) => {
+                    (now.saturating_duration_since(progress.last_at) >= *inactivity)
+                        .then_some(CompletionReason::Inactivity)
+                }
+                _ => None,
+            })
+    }
+}
+
+impl FromStr for CompletionStrategy {
+    type Err = String;
+
+    /// Parses conditions separated by `|`, such as `count:10|timeout:30s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conditions = s
+            .split('|')
+            .map(str::parse)
+            .collect::<Result<Vec<CompletionCondition>, String>>()?;
+        Ok(CompletionStrategy::new(conditions))
+    }
+}
+
+fn count_header(value: &AMQPValue) -> Option<u64> {
+    match value {
+        AMQPValue::ShortShortUInt(value) => Some(u64::from(*value)),
+        AMQPValue::ShortUInt(value) => Some(u64::from(*value)),
+        AMQPValue::LongUInt(value) => Some(u64::from(*value)),
+        AMQPValue::ShortShortInt(value) => u64::try_from(*value).ok(),
+        AMQPValue::ShortInt(value) => u64::try_from(*value).ok(),
+        AMQPValue::LongInt(value) => u64::try_from(*value).ok(),
+        AMQPValue::LongLongInt(value) => u64::try_from(*value).ok(),
+        AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok()?.parse().ok(),
+        AMQPValue::ShortString(value) => value.as_str().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(received: usize, expected: Option<u64>) -> Progress {
+        let now = Instant::now();
+        Progress {
+            received,
+            expected,
+            first_at: now,
+            last_at: now,
+        }
+    }
+
+    #[test]
+    fn strategies_parse_from_their_conditions() {
+        let strategy: CompletionStrategy = "count:10|timeout:30s|json:end_of_batch=true"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            strategy,
+            CompletionStrategy::new(vec![
+                CompletionCondition::Count(10),
+                CompletionCondition::Timeout(Duration::from_secs(30)),
+                CompletionCondition::JsonFieldEquals {
+                    pointer: "/end_of_batch".to_string(),
+                    value: Value::Bool(true),
+                },
+            ])
+        );
+        assert!("count:0".parse::<CompletionStrategy>().is_err());
+        assert!("count".parse::<CompletionStrategy>().is_err());
+        assert!("sometime:5".parse::<CompletionStrategy>().is_err());
+    }
+
+    #[test]
+    fn message_conditions_complete_on_arrival() {
+        let strategy: CompletionStrategy = "count:3|header:expected|contains:EOF".parse().unwrap();
+
+        assert_eq!(strategy.on_message(&progress(2, None), "a"), None);
+        assert_eq!(
+            strategy.on_message(&progress(3, None), "a"),
+            Some(CompletionReason::Count)
+        );
+        assert_eq!(
+            strategy.on_message(&progress(2, Some(2)), "a"),
+            Some(CompletionReason::ExpectedCount)
+        );
+        assert_eq!(
+            strategy.on_message(&progress(1, None), "line EOF"),
+            Some(CompletionReason::Predicate)
+        );
+
+        let marker: CompletionStrategy = "json:/batch/last=true".parse().unwrap();
+        assert_eq!(
+            marker.on_message(&progress(1, None), r#"{"batch": {"last": true}}"#),
+            Some(CompletionReason::Predicate)
+        );
+        assert_eq!(
+            marker.on_message(&progress(1, None), r#"{"batch": {"last": false}}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn time_conditions_complete_on_tick() {
+        let strategy: CompletionStrategy = "timeout:10s|inactivity:2s".parse().unwrap();
+        let started = Instant::now();
+        let progress = Progress {
+            received: 1,
+            expected: None,
+            first_at: started,
+            last_at: started + Duration::from_secs(5),
+        };
+
+        assert_eq!(
+            strategy.on_tick(&progress, started + Duration::from_secs(6)),
+            None
+        );
+        assert_eq!(
+            strategy.on_tick(&progress, started + Duration::from_secs(7)),
+            Some(CompletionReason::Inactivity)
+        );
+
+        let timeout: CompletionStrategy = "timeout:10s".parse().unwrap();
+        assert_eq!(
+            timeout.on_tick(&progress, started + Duration::from_secs(10)),
+            Some(CompletionReason::Timeout)
+        );
+    }
+
+    #[test]
+    fn expected_count_is_read_from_the_header() {
+        let strategy: CompletionStrategy = "header:expected".parse().unwrap();
+        let mut headers = FieldTable::default();
+        assert_eq!(strategy.expected_count(Some(&headers)), None);
+
+        headers.insert("expected".into(), AMQPValue::LongLongInt(4));
+        assert_eq!(strategy.expected_count(Some(&headers)), Some(4));
+
+        headers.insert("expected".into(), AMQPValue::LongString("5".into()));
+        assert_eq!(strategy.expected_count(Some(&headers)), Some(5));
+    }
+}
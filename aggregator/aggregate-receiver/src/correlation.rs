@@ -0,0 +1,246 @@
+use std::{fmt, str::FromStr};
+
+use lapin::types::{AMQPValue, FieldTable};
+use serde_json::Value;
+
+/// Where the correlation key of a message is read from, which decides the group it is aggregated into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorrelationSource {
+    /// A message header, such as `correlation_id`.
+    Header(String),
+    /// A field of a JSON payload, addressed by a JSON pointer such as `/order/id`.
+    JsonField(String),
+    /// The same key for every message, such as to count all messages per time window.
+    Constant(String),
+}
+
+impl FromStr for CorrelationSource {
+    type Err = String;
+
+    /// Parses `header:<name>`, `json:<pointer>` or `constant:<key>`. A pointer without a leading `/` names a top-level field.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, name) = s.trim().split_once(':').ok_or_else(|| {
+            format!(
+                "Invalid correlation source '{}', expected 'header:<name>', 'json:<pointer>' or 'constant:<key>'",
+                s
+            )
+        })?;
+        if name.is_empty() {
+            return Err(format!("Correlation source '{}' names no {}", s, kind));
+        }
+
+        match kind.to_ascii_lowercase().as_str() {
+            "header" => Ok(CorrelationSource::Header(name.to_string())),
+            "json" if name.starts_with('/') => Ok(CorrelationSource::JsonField(name.to_string())),
+            "json" => Ok(CorrelationSource::JsonField(format!("/{}", name))),
+            "constant" => Ok(CorrelationSource::Constant(name.to_string())),
+            other => Err(format!(
+                "Unknown correlation source '{}', expected 'header', 'json' or 'constant'",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CorrelationSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorrelationSource::Header(name) => write!(f, "header '{}'", name),
+            CorrelationSource::JsonField(pointer) => write!(f, "JSON field '{}'", pointer),
+            CorrelationSource::Constant(key) => write!(f, "constant '{}'", key),
+        }
+    }
+}
+
+/// Why the correlation key of a message could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorrelationError {
+    Missing(CorrelationSource),
+    WrongType {
+        source: CorrelationSource,
+        found: String,
+    },
+    InvalidUtf8(CorrelationSource),
+    InvalidJson(String),
+    Empty(CorrelationSource),
+}
+
+impl CorrelationError {
+    /// A short machine readable identifier of the error.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            CorrelationError::Missing(_) => "missing_correlation_key",
+            CorrelationError::WrongType { .. } => "wrong_correlation_key_type",
+            CorrelationError::InvalidUtf8(_) => "invalid_utf8_correlation_key",
+            CorrelationError::InvalidJson(_) => "invalid_json",
+            CorrelationError::Empty(_) => "empty_correlation_key",
+        }
+    }
+}
+
+impl fmt::Display for CorrelationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorrelationError::Missing(source) => write!(f, "{} is missing", source),
+            CorrelationError::WrongType { source, found } => {
+                write!(f, "{} has the unsupported type {}", source, found)
+            }
+            CorrelationError::InvalidUtf8(source) => write!(f, "{} is not valid UTF-8", source),
+            CorrelationError::InvalidJson(e) => write!(f, "payload is not a JSON document: {}", e),
+            CorrelationError::Empty(source) => write!(f, "{} is empty", source),
+        }
+    }
+}
+
+impl std::error::Error for CorrelationError {}
+
+impl CorrelationSource {
+    /// Reads the correlation key of a message.
+    ///
+    /// A header may be a LongString, a ShortString, or any integer type, while a JSON field may be a string or a number.
+    pub fn extract(
+        &self,
+        headers: Option<&FieldTable>,
+        payload: &[u8],
+    ) -> Result<String, CorrelationError> {
+        let key = match self {
+            CorrelationSource::Header(name) => {
+                let value = headers
+                    .and_then(|headers| headers.inner().get(name.as_str()))
+                    .ok_or_else(|| CorrelationError::Missing(self.clone()))?;
+                self.header_key(value)?
+            }
+            CorrelationSource::JsonField(pointer) => {
+                let document: Value = serde_json::from_slice(payload)
+                    .map_err(|e| CorrelationError::InvalidJson(e.to_string()))?;
+                match document.pointer(pointer) {
+                    Some(Value::String(value)) => value.clone(),
+                    Some(Value::Number(value)) => value.to_string(),
+                    Some(Value::Null) | None => {
+                        return Err(CorrelationError::Missing(self.clone()))
+                    }
+                    Some(other) => {
+                        return Err(CorrelationError::WrongType {
+                            source: self.clone(),
+                            found: json_type(other).to_string(),
+                        })
+                    }
+                }
+            }
+            CorrelationSource::Constant(key) => key.clone(),
+        };
+
+        if key.is_empty() {
+            return Err(CorrelationError::Empty(self.clone()));
+        }
+        Ok(key)
+    }
+
+    fn header_key(&self, value: &AMQPValue) -> Result<String, CorrelationError> {
+        match value {
+            AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes())
+                .map(String::from)
+                .map_err(|_| CorrelationError::InvalidUtf8(self.clone())),
+            AMQPValue::ShortString(value) => Ok(value.as_str().to_string()),
+            AMQPValue::ShortShortUInt(value) => Ok(value.to_string()),
+            AMQPValue::ShortUInt(value) => Ok(value.to_string()),
+            AMQPValue::LongUInt(value) => Ok(value.to_string()),
+            AMQPValue::ShortShortInt(value) => Ok(value.to_string()),
+            AMQPValue::ShortInt(value) => Ok(value.to_string()),
+            AMQPValue::LongInt(value) => Ok(value.to_string()),
+            AMQPValue::LongLongInt(value) => Ok(value.to_string()),
+            other => Err(CorrelationError::WrongType {
+                source: self.clone(),
+                found: other.get_type().to_string(),
+            }),
+        }
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_parse_from_their_description() {
+        assert_eq!(
+            "header:correlation_id".parse(),
+            Ok(CorrelationSource::Header("correlation_id".to_string()))
+        );
+        assert_eq!(
+            "json:order_id".parse(),
+            Ok(CorrelationSource::JsonField("/order_id".to_string()))
+        );
+        assert_eq!(
+            "JSON:/order/id".parse(),
+            Ok(CorrelationSource::JsonField("/order/id".to_string()))
+        );
+        assert_eq!(
+            "constant:bookings".parse(),
+            Ok(CorrelationSource::Constant("bookings".to_string()))
+        );
+        assert!("order_id".parse::<CorrelationSource>().is_err());
+        assert!("header:".parse::<CorrelationSource>().is_err());
+        assert!("body:id".parse::<CorrelationSource>().is_err());
+    }
+
+    #[test]
+    fn keys_are_read_from_headers() {
+        let source = CorrelationSource::Header("correlation_id".to_string());
+        let mut headers = FieldTable::default();
+
+        assert_eq!(
+            source.extract(Some(&headers), b""),
+            Err(CorrelationError::Missing(source.clone()))
+        );
+
+        headers.insert("correlation_id".into(), AMQPValue::LongString("abc".into()));
+        assert_eq!(source.extract(Some(&headers), b""), Ok("abc".to_string()));
+
+        headers.insert("correlation_id".into(), AMQPValue::LongLongInt(42));
+        assert_eq!(source.extract(Some(&headers), b""), Ok("42".to_string()));
+
+        headers.insert("correlation_id".into(), AMQPValue::Boolean(true));
+        assert_eq!(
+            source.extract(Some(&headers), b"").unwrap_err().reason(),
+            "wrong_correlation_key_type"
+        );
+    }
+
+    #[test]
+    fn keys_are_read_from_json_fields() {
+        let source: CorrelationSource = "json:/order/id".parse().unwrap();
+
+        assert_eq!(
+            source.extract(None, br#"{"order": {"id": "o-1"}}"#),
+            Ok("o-1".to_string())
+        );
+        assert_eq!(
+            source.extract(None, br#"{"order": {"id": 7}}"#),
+            Ok("7".to_string())
+        );
+        assert_eq!(
+            source.extract(None, br#"{"order": {}}"#),
+            Err(CorrelationError::Missing(source.clone()))
+        );
+        assert_eq!(
+            source.extract(None, br#"{"order": {"id": ""}}"#),
+            Err(CorrelationError::Empty(source.clone()))
+        );
+        assert_eq!(
+            source.extract(None, b"not json").unwrap_err().reason(),
+            "invalid_json"
+        );
+    }
+}
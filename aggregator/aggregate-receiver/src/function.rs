@@ -0,0 +1,448 @@
+use std::{fmt, sync::Arc};
+
+use lapin::types::{AMQPValue, FieldTable};
+use serde_json::{Map, Value};
+
+/// A message held by a buffer until it is aggregated.
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub payload: String,
+    pub headers: Option<FieldTable>,
+}
+
+/// Why a part cannot be aggregated by an [`AggregationFunction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AggregationError {
+    InvalidJson(String),
+    NotAnObject,
+    NotANumber { pointer: String },
+    MissingTimestamp { header: String },
+}
+
+impl AggregationError {
+    /// A short machine readable identifier of the error.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AggregationError::InvalidJson(_) => "invalid_json",
+            AggregationError::NotAnObject => "not_a_json_object",
+            AggregationError::NotANumber { .. } => "not_a_number",
+            AggregationError::MissingTimestamp { .. } => "missing_timestamp",
+        }
+    }
+}
+
+impl fmt::Display for AggregationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregationError::InvalidJson(e) => write!(f, "payload is not a JSON document: {}", e),
+            AggregationError::NotAnObject => write!(f, "payload is not a JSON object"),
+            AggregationError::NotANumber { pointer } => {
+                write!(f, "JSON field '{}' is not a number", pointer)
+            }
+            AggregationError::MissingTimestamp { header } => {
+                write!(f, "header '{}' is not an integer timestamp", header)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AggregationError {}
+
+/// Reduces the messages buffered for a correlation key to the payload of their aggregate, such as their sum, their
+/// count, or a JSON array of them.
+///
+/// [`AggregationFunction::check`] runs before a message is buffered. A message the function could not reduce, such as
+/// one without the number a sum reads, is then rejected when it arrives, and the buffer only ever holds messages that
+/// [`AggregationFunction::aggregate`] can handle once the buffer completes.
+pub trait AggregationFunction: fmt::Debug + Send + Sync {
+    /// Checks that the function can reduce `part`, such as that it holds the JSON number or header it reads.
+    fn check(&self, _part: &Part) -> Result<(), AggregationError> {
+        Ok(())
+    }
+
+    /// Reduces the buffered messages, in the order they arrived, to the payload of the aggregate.
+    fn aggregate(&self, parts: &[Part]) -> String;
+
+    /// Content type the aggregate is published with, such as `application/json` for a sum. `None` leaves it unset,
+    /// as for concatenated payloads of any type.
+    fn content_type(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Concatenates the payloads.
+#[derive(Debug, Default)]
+pub struct Concat;
+
+impl AggregationFunction for Concat {
+    fn aggregate(&self, parts: &[Part]) -> String {
+        parts.iter().map(|part| part.payload.as_str()).collect()
+    }
+}
+
+/// Counts the payloads, whatever they are.
+#[derive(Debug, Default)]
+pub struct Count;
+
+impl AggregationFunction for Count {
+    fn aggregate(&self, parts: &[Part]) -> String {
+        parts.len().to_string()
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        Some("application/json")
+    }
+}
+
+/// Collects the payloads, each of which has to be a JSON document, into a JSON array.
+#[derive(Debug, Default)]
+pub struct JsonArray;
+
+impl AggregationFunction for JsonArray {
+    fn check(&self, part: &Part) -> Result<(), AggregationError> {
+        parse(part).map(|_| ())
+    }
+
+    fn aggregate(&self, parts: &[Part]) -> String {
+        // `check` parsed every payload when it was buffered, so the array is built from their text as it is
+        let payloads: Vec<&str> = parts.iter().map(|part| part.payload.as_str()).collect();
+        format!("[{}]", payloads.join(","))
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        Some("application/json")
+    }
+}
+
+/// Merges the payloads, each of which has to be a JSON object, into a single object.
+///
+/// Objects are merged recursively, while any other value of a later part replaces that of an earlier one.
+#[derive(Debug, Default)]
+pub struct JsonMerge;
+
+impl AggregationFunction for JsonMerge {
+    fn check(&self, part: &Part) -> Result<(), AggregationError> {
+        match parse(part)? {
+            Value::Object(_) => Ok(()),
+            _ => Err(AggregationError::NotAnObject),
+        }
+    }
+
+    fn aggregate(&self, parts: &[Part]) -> String {
+        let mut merged = Value::Object(Map::new());
+        for part in parts {
+            if let Ok(value) = parse(part) {
+                deep_merge(&mut merged, value);
+            }
+        }
+        merged.to_string()
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        Some("application/json")
+    }
+}
+
+fn deep_merge(target: &mut Value, source: Value) {
+    match (target, source) {
+        (Value::Object(target), Value::Object(source)) => {
+            for (key, value) in source {
+                match target.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, source) => *target = source,
+    }
+}
+
+/// How [`Numeric`] folds the numbers it reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericOperation {
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+/// Folds a number read from every payload, each of which has to be a JSON document, into a single JSON number.
+#[derive(Debug)]
+pub struct Numeric {
+    pub operation: NumericOperation,
+    /// JSON pointer to the number, such as `/amount`.
+    pub pointer: String,
+}
+
+impl Numeric {
+    fn number(&self, part: &Part) -> Result<Value, AggregationError> {
+        match parse(part)?.pointer(&self.pointer) {
+            Some(Value::Number(number)) => Ok(Value::Number(number.clone())),
+            _ => Err(AggregationError::NotANumber {
+                pointer: self.pointer.clone(),
+            }),
+        }
+    }
+}
+
+impl AggregationFunction for Numeric {
+    fn check(&self, part: &Part) -> Result<(), AggregationError> {
+        self.number(part).map(|_| ())
+    }
+
+    fn aggregate(&self, parts: &[Part]) -> String {
+        let numbers: Vec<Value> = parts
+            .iter()
+            .filter_map(|part| self.number(part).ok())
+            .collect();
+        if numbers.is_empty() {
+            return Value::Null.to_string();
+        }
+
+        // Integers stay integers, unless they are averaged or overflow
+        let integers: Option<Vec<i64>> = numbers.iter().map(Value::as_i64).collect();
+        let integer_result = integers.and_then(|integers| match self.operation {
+            NumericOperation::Sum => integers
+                .iter()
+                .try_fold(0i64, |sum, &value| sum.checked_add(value)),
+            NumericOperation::Min => integers.iter().copied().min(),
+            NumericOperation::Max => integers.iter().copied().max(),
+            NumericOperation::Avg => None,
+        });
+        if let Some(result) = integer_result {
+            return result.to_string();
+        }
+
+        let floats: Vec<f64> = numbers.iter().filter_map(Value::as_f64).collect();
+        let result = match self.operation {
+            NumericOperation::Sum => floats.iter().sum(),
+            NumericOperation::Min => floats.iter().copied().fold(f64::INFINITY, f64::min),
+            NumericOperation::Max => floats.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            NumericOperation::Avg => floats.iter().sum::<f64>() / floats.len() as f64,
+        };
+        Value::from(result).to_string()
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        Some("application/json")
+    }
+}
+
+/// Keeps only the payload with the latest timestamp, read from an integer header such as milliseconds since
+/// the epoch. Of payloads with the same timestamp, the one that arrived last is kept.
+#[derive(Debug)]
+pub struct LatestByTimestamp {
+    pub header: String,
+}
+
+impl LatestByTimestamp {
+    fn timestamp(&self, part: &Part) -> Result<i64, AggregationError> {
+        timestamp_header(part.headers.as_ref(), &self.header).ok_or_else(|| {
+            AggregationError::MissingTimestamp {
+                header: self.header.clone(),
+            }
+        })
+    }
+}
+
+impl AggregationFunction for LatestByTimestamp {
+    fn check(&self, part: &Part) -> Result<(), AggregationError> {
+        self.timestamp(part).map(|_| ())
+    }
+
+    fn aggregate(&self, parts: &[Part]) -> String {
+        parts
+            .iter()
+            .filter_map(|part| Some((self.timestamp(part).ok()?, part)))
+            .max_by_key(|(timestamp, _)| *timestamp)
+            .map(|(_, part)| part.payload.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Reads an integer timestamp, such as milliseconds since the epoch, from the header `name`.
+pub(crate) fn timestamp_header(headers: Option<&FieldTable>, name: &str) -> Option<i64> {
+    match headers?.inner().get(name)? {
+        AMQPValue::Timestamp(value) => i64::try_from(*value).ok(),
+        AMQPValue::LongLongInt(value) => Some(*value),
+        AMQPValue::LongInt(value) => Some(i64::from(*value)),
+        AMQPValue::LongUInt(value) => Some(i64::from(*value)),
+        AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok()?.parse().ok(),
+        _ => None,
+    }
+}
+
+fn parse(part: &Part) -> Result<Value, AggregationError> {
+    serde_json::from_str(&part.payload).map_err(|e| AggregationError::InvalidJson(e.to_string()))
+}
+
+/// Looks up one of the built-in functions by the name it is configured with, such as `concat` or `sum:/amount`.
+pub fn from_name(name: &str) -> Result<Arc<dyn AggregationFunction>, String> {
+    let name = name.trim();
+    let (kind, argument) = match name.split_once(':') {
+        Some((kind, argument)) => (kind, Some(argument)),
+        None => (name, None),
+    };
+    let pointer = |argument: &str| match argument.starts_with('/') {
+        true => argument.to_string(),
+        false => format!("/{}", argument),
+    };
+
+    let numeric = |operation| -> Result<Arc<dyn AggregationFunction>, String> {
+        match argument {
+            Some(argument) if !argument.is_empty() => Ok(Arc::new(Numeric {
+                operation,
+                pointer: pointer(argument),
+            })),
+            _ => Err(format!(
+                "Aggregation function '{}' needs a JSON pointer, such as '{}:/amount'",
+                kind, kind
+            )),
+        }
+    };
+
+    match (kind.to_ascii_lowercase().as_str(), argument) {
+        ("concat", None) => Ok(Arc::new(Concat)),
+        ("count", None) => Ok(Arc::new(Count)),
+        ("json-array", None) => Ok(Arc::new(JsonArray)),
+        ("json-merge", None) => Ok(Arc::new(JsonMerge)),
+        ("sum", _) => numeric(NumericOperation::Sum),
+        ("min", _) => numeric(NumericOperation::Min),
+        ("max", _) => numeric(NumericOperation::Max),
+        ("avg", _) => numeric(NumericOperation::Avg),
+        ("latest", Some(header)) if !header.is_empty() => Ok(Arc::new(LatestByTimestamp {
+            header: header.to_string(),
+        })),
+        ("latest", _) => Err(String::from(
+            "Aggregation function 'latest' needs a timestamp header, such as 'latest:timestamp'",
+        )),
+        _ => Err(format!(
+            "Unknown aggregation function '{}', expected 'concat', 'count', 'json-array', 'json-merge', 'sum:<pointer>', 'min:<pointer>', 'max:<pointer>', 'avg:<pointer>' or 'latest:<header>'",
+            name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(payloads: &[&str]) -> Vec<Part> {
+        payloads
+            .iter()
+            .map(|payload| Part {
+                payload: payload.to_string(),
+                headers: None,
+            })
+            .collect()
+    }
+
+    fn json(aggregate: String) -> Value {
+        serde_json::from_str(&aggregate).unwrap()
+    }
+
+    #[test]
+    fn concat_and_json_array_collect_the_payloads() {
+        let parts = parts(&[r#"{"a":1}"#, "2"]);
+        assert_eq!(Concat.aggregate(&parts), r#"{"a":1}2"#);
+        assert_eq!(Count.aggregate(&parts), "2");
+        assert_eq!(
+            json(JsonArray.aggregate(&parts)),
+            serde_json::json!([{"a": 1}, 2])
+        );
+        assert!(JsonArray.check(&parts[0]).is_ok());
+        assert_eq!(
+            JsonArray
+                .check(&self::parts(&["{"])[0])
+                .unwrap_err()
+                .reason(),
+            "invalid_json"
+        );
+    }
+
+    #[test]
+    fn json_merge_merges_objects_recursively() {
+        let parts = parts(&[
+            r#"{"order": {"id": 1, "lines": [1]}, "status": "open"}"#,
+            r#"{"order": {"total": 5, "lines": [2]}, "status": "paid"}"#,
+        ]);
+        assert_eq!(
+            json(JsonMerge.aggregate(&parts)),
+            serde_json::json!({"order": {"id": 1, "total": 5, "lines": [2]}, "status": "paid"})
+        );
+        assert_eq!(
+            JsonMerge.check(&self::parts(&["[1]"])[0]),
+            Err(AggregationError::NotAnObject)
+        );
+    }
+
+    #[test]
+    fn numeric_functions_fold_a_json_field() {
+        let parts = parts(&[
+            r#"{"line": {"amount": 10}}"#,
+            r#"{"line": {"amount": 5}}"#,
+            r#"{"line": {"amount": 30}}"#,
+        ]);
+        let fold = |name: &str| from_name(name).unwrap().aggregate(&parts);
+
+        assert_eq!(fold("sum:/line/amount"), "45");
+        assert_eq!(fold("min:/line/amount"), "5");
+        assert_eq!(fold("max:/line/amount"), "30");
+        assert_eq!(fold("avg:/line/amount"), "15.0");
+
+        let fractions = self::parts(&[r#"{"amount": 1.5}"#, r#"{"amount": 2}"#]);
+        assert_eq!(
+            from_name("sum:amount").unwrap().aggregate(&fractions),
+            "3.5"
+        );
+
+        let missing = self::parts(&[r#"{"line": {}}"#]);
+        assert_eq!(
+            from_name("sum:/line/amount")
+                .unwrap()
+                .check(&missing[0])
+                .unwrap_err()
+                .reason(),
+            "not_a_number"
+        );
+    }
+
+    #[test]
+    fn latest_keeps_the_payload_with_the_latest_timestamp() {
+        let part = |payload: &str, timestamp: i64| {
+            let mut headers = FieldTable::default();
+            headers.insert("timestamp".into(), AMQPValue::LongLongInt(timestamp));
+            Part {
+                payload: payload.to_string(),
+                headers: Some(headers),
+            }
+        };
+        let latest = from_name("latest:timestamp").unwrap();
+        let parts = vec![part("b", 20), part("c", 10), part("a", 20)];
+
+        assert_eq!(latest.aggregate(&parts), "a");
+        assert_eq!(
+            latest.check(&self::parts(&["x"])[0]).unwrap_err().reason(),
+            "missing_timestamp"
+        );
+    }
+
+    #[test]
+    fn from_name_knows_the_built_in_functions() {
+        for name in [
+            "concat",
+            "count",
+            "JSON-Array",
+            "json-merge",
+            "avg:/total",
+            "latest:ts",
+        ] {
+            assert!(from_name(name).is_ok(), "{}", name);
+        }
+        for name in ["sum", "sum:", "latest", "concat:x", "median:/total"] {
+            assert!(from_name(name).is_err(), "{}", name);
+        }
+    }
+}
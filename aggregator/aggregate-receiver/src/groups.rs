@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+/// A setting that may differ per aggregation group, along with the value used for messages of other groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerGroup<T> {
+    pub default: T,
+    pub groups: HashMap<String, T>,
+}
+
+impl<T> PerGroup<T> {
+    /// A setting that is the same for every group.
+    pub fn new(default: T) -> PerGroup<T> {
+        PerGroup {
+            default,
+            groups: HashMap::new(),
+        }
+    }
+
+    /// The setting of the aggregation group, or the default one.
+    pub fn for_group(&self, group: Option<&str>) -> &T {
+        group
+            .and_then(|group| self.groups.get(group))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Parses settings per aggregation group separated by `;`, such as `billing=count:10;bookings=inactivity:5m`,
+/// with `parse` parsing the setting of each group.
+pub fn parse_per_group<T>(
+    s: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<HashMap<String, T>, String> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            let (name, setting) = group.split_once('=').ok_or_else(|| {
+                format!(
+                    "Invalid group setting '{}', expected '<group>=<setting>'",
+                    group
+                )
+            })?;
+            Ok((name.trim().to_string(), parse(setting)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_have_settings_of_their_own() {
+        let settings = PerGroup {
+            default: 2,
+            groups: parse_per_group("billing=10; bookings = 5", |s| {
+                s.trim().parse::<u32>().map_err(|e| e.to_string())
+            })
+            .unwrap(),
+        };
+
+        assert_eq!(settings.for_group(Some("billing")), &10);
+        assert_eq!(settings.for_group(Some("bookings")), &5);
+        assert_eq!(settings.for_group(Some("other")), &2);
+        assert_eq!(settings.for_group(None), &2);
+        assert!(parse_per_group("billing", |s| Ok(s.to_string())).is_err());
+    }
+}
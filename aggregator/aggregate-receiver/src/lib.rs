@@ -0,0 +1,14 @@
+//! The aggregator, which collects messages that belong together and combines them into a single aggregate.
+//!
+//! The binaries wire these modules to RabbitMQ: `aggregate-receiver` aggregates the messages of a queue, while
+//! `scatter-gather` scatters requests to a set of recipients and gathers their replies.
+
+pub mod aggregator;
+pub mod amqp;
+pub mod completion;
+pub mod config;
+pub mod correlation;
+pub mod function;
+pub mod groups;
+pub mod scatter;
+pub mod window;
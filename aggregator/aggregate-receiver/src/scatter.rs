@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde_json::{Map, Value};
+
+/// The request a scatter was started for, which the combined response goes back to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub reply_to: String,
+    /// Correlation id of the request, which the combined response carries over.
+    pub correlation_id: Option<String>,
+    /// Delivery tag of the request, which stays unacknowledged until the combined response is published.
+    pub delivery_tag: u64,
+}
+
+/// The replies gathered for a request, once every recipient replied or the deadline passed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gathered {
+    /// The id of the scatter, which the correlation ids of the scattered requests and their replies start with.
+    pub scatter_id: String,
+    pub request: Request,
+    /// The payload of every reply by its recipient, in the order the recipients were configured.
+    pub replies: Vec<(String, String)>,
+    /// The recipients that did not reply before the deadline.
+    pub timed_out: Vec<String>,
+}
+
+impl Gathered {
+    /// The combined response, a JSON object with the reply of every recipient and those that timed out.
+    ///
+    /// A reply that is a JSON document is embedded as it is, while any other reply is embedded as a string.
+    pub fn response(&self) -> String {
+        let replies: Map<String, Value> = self
+            .replies
+            .iter()
+            .map(|(recipient, payload)| {
+                let reply = serde_json::from_str(payload)
+                    .unwrap_or_else(|_| Value::String(payload.clone()));
+                (recipient.clone(), reply)
+            })
+            .collect();
+        serde_json::json!({
+            "correlation_id": self.scatter_id,
+            "complete": self.timed_out.is_empty(),
+            "replies": replies,
+            "timed_out": self.timed_out,
+        })
+        .to_string()
+    }
+}
+
+/// Why a reply cannot be gathered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplyError {
+    /// The scatter is unknown, most likely because its deadline passed already.
+    UnknownScatter(String),
+    UnknownRecipient(String),
+    Duplicate(String),
+}
+
+impl ReplyError {
+    /// A short machine readable identifier of the error.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ReplyError::UnknownScatter(_) => "unknown_scatter",
+            ReplyError::UnknownRecipient(_) => "unknown_recipient",
+            ReplyError::Duplicate(_) => "duplicate_reply",
+        }
+    }
+}
+
+impl fmt::Display for ReplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplyError::UnknownScatter(scatter_id) => write!(
+                f,
+                "no scatter with the correlation id '{}' is waiting for replies",
+                scatter_id
+            ),
+            ReplyError::UnknownRecipient(recipient) => {
+                write!(f, "'{}' is not a recipient of the scatter", recipient)
+            }
+            ReplyError::Duplicate(recipient) => {
+                write!(f, "'{}' replied to the scatter already", recipient)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplyError {}
+
+/// The correlation id of the request scattered to `recipient`, which its reply carries back.
+///
+/// Responders echo the correlation id of a request in their reply, and nothing else, so the recipient a reply is
+/// from is told by its correlation id.
+pub fn correlation_id(scatter_id: &str, recipient: &str) -> String {
+    format!("{}:{}", scatter_id, recipient)
+}
+
+/// Splits the correlation id of a reply into its scatter id and recipient. Queue names may contain colons,
+/// while the scatter id does not.
+fn split_correlation_id(correlation_id: &str) -> Option<(&str, &str)> {
+    correlation_id.split_once(':')
+}
+
+/// A scatter that is waiting for replies.
+#[derive(Debug)]
+struct Scatter {
+    request: Request,
+    recipients: Vec<String>,
+    replies: HashMap<String, String>,
+    deadline: Instant,
+}
+
+/// Gathers the replies to requests that were scattered to a set of recipients, until every recipient replied
+/// or the deadline of the request passed.
+#[derive(Debug, Default)]
+pub struct Gatherer {
+    scatters: HashMap<String, Scatter>,
+}
+
+impl Gatherer {
+    pub fn new() -> Gatherer {
+        Gatherer::default()
+    }
+
+    /// Starts waiting for the replies of the recipients to a request that was scattered with `scatter_id`.
+    pub fn start(
+        &mut self,
+        scatter_id: String,
+        request: Request,
+        recipients: Vec<String>,
+        timeout: Duration,
+        now: Instant,
+    ) {
+        self.scatters.insert(
+            scatter_id,
+            Scatter {
+                request,
+                recipients,
+                replies: HashMap::new(),
+                deadline: now + timeout,
+            },
+        );
+    }
+
+    /// Adds a reply by its correlation id, as made by [`correlation_id`], and returns the gathered replies once every
+    /// recipient replied.
+    pub fn reply(
+        &mut self,
+        correlation_id: &str,
+        payload: String,
+    ) -> Result<Option<Gathered>, ReplyError> {
+        let (scatter_id, recipient) = split_correlation_id(correlation_id)
+            .ok_or_else(|| ReplyError::UnknownScatter(correlation_id.to_string()))?;
+        let scatter = self
+            .scatters
+            .get_mut(scatter_id)
+            .ok_or_else(|| ReplyError::UnknownScatter(scatter_id.to_string()))?;
+        if !scatter.recipients.iter().any(|known| known == recipient) {
+            return Err(ReplyError::UnknownRecipient(recipient.to_string()));
+        }
+        if scatter.replies.contains_key(recipient) {
+            return Err(ReplyError::Duplicate(recipient.to_string()));
+        }
+        scatter.replies.insert(recipient.to_string(), payload);
+
+        if scatter.replies.len() < scatter.recipients.len() {
+            return Ok(None);
+        }
+        Ok(self.gather(scatter_id))
+    }
+
+    /// Gathers the replies of every scatter whose deadline passed by the time `now`.
+    pub fn expire(&mut self, now: Instant) -> Vec<Gathered> {
+        let expired: Vec<String> = self
+            .scatters
+            .iter()
+            .filter(|(_, scatter)| scatter.deadline <= now)
+            .map(|(scatter_id, _)| scatter_id.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|scatter_id| self.gather(scatter_id))
+            .collect()
+    }
+
+    /// How many scatters are waiting for replies.
+    pub fn pending(&self) -> usize {
+        self.scatters.len()
+    }
+
+    fn gather(&mut self, scatter_id: &str) -> Option<Gathered> {
+        let mut scatter = self.scatters.remove(scatter_id)?;
+        let mut replies = Vec::new();
+        let mut timed_out = Vec::new();
+        for recipient in scatter.recipients {
+            match scatter.replies.remove(&recipient) {
+                Some(payload) => replies.push((recipient, payload)),
+                None => timed_out.push(recipient),
+            }
+        }
+        Some(Gathered {
+            scatter_id: scatter_id.to_string(),
+            request: scatter.request,
+            replies,
+            timed_out,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::BasicProperties;
+
+    fn reply(
+        gatherer: &mut Gatherer,
+        recipient: &str,
+        payload: &str,
+    ) -> Result<Option<Gathered>, ReplyError> {
+        gatherer.reply(&correlation_id("s-1", recipient), String::from(payload))
+    }
+
+    fn gatherer(now: Instant) -> Gatherer {
+        let mut gatherer = Gatherer::new();
+        gatherer.start(
+            String::from("s-1"),
+            Request {
+                reply_to: String::from("q_client"),
+                correlation_id: Some(String::from("c-1")),
+                delivery_tag: 7,
+            },
+            vec![String::from("q_a"), String::from("q_b")],
+            Duration::from_secs(5),
+            now,
+        );
+        gatherer
+    }
+
+    #[test]
+    fn replies_are_gathered_once_every_recipient_replied() {
+        let mut gatherer = gatherer(Instant::now());
+
+        assert_eq!(reply(&mut gatherer, "q_b", "2"), Ok(None));
+        assert_eq!(
+            reply(&mut gatherer, "q_b", "2"),
+            Err(ReplyError::Duplicate(String::from("q_b")))
+        );
+        assert_eq!(
+            reply(&mut gatherer, "q_c", "3").unwrap_err().reason(),
+            "unknown_recipient"
+        );
+
+        let gathered = reply(&mut gatherer, "q_a", "text").unwrap().unwrap();
+        assert_eq!(gathered.request.delivery_tag, 7);
+        assert_eq!(
+            serde_json::from_str::<Value>(&gathered.response()).unwrap(),
+            serde_json::json!({
+                "correlation_id": "s-1",
+                "complete": true,
+                "replies": {"q_a": "text", "q_b": 2},
+                "timed_out": [],
+            })
+        );
+        assert_eq!(gatherer.pending(), 0);
+        assert_eq!(
+            reply(&mut gatherer, "q_a", "late").unwrap_err().reason(),
+            "unknown_scatter"
+        );
+        assert_eq!(
+            gatherer
+                .reply("s-1", String::from("no recipient"))
+                .unwrap_err()
+                .reason(),
+            "unknown_scatter"
+        );
+    }
+
+    #[test]
+    fn replies_are_told_apart_by_their_correlation_id_alone() {
+        let mut gatherer = Gatherer::new();
+        gatherer.start(
+            String::from("s-2"),
+            Request {
+                reply_to: String::from("q_client"),
+                correlation_id: None,
+                delivery_tag: 8,
+            },
+            vec![String::from("q_a"), String::from("rpc:q_b")],
+            Duration::from_secs(5),
+            Instant::now(),
+        );
+
+        // Responders echo the correlation id of the request in a reply of their own, without any headers
+        let mut gathered = None;
+        for recipient in ["rpc:q_b", "q_a"] {
+            let request = BasicProperties::default()
+                .with_correlation_id(correlation_id("s-2", recipient).into());
+            let reply = BasicProperties::default()
+                .with_correlation_id(request.correlation_id().clone().unwrap());
+            assert!(reply.headers().is_none());
+
+            let correlation_id = reply.correlation_id().as_ref().unwrap().as_str();
+            gathered = gatherer
+                .reply(correlation_id, format!("\"from {}\"", recipient))
+                .unwrap();
+        }
+
+        let gathered = gathered.unwrap();
+        assert!(gathered.timed_out.is_empty());
+        assert_eq!(
+            gathered.replies,
+            vec![
+                (String::from("q_a"), String::from("\"from q_a\"")),
+                (String::from("rpc:q_b"), String::from("\"from rpc:q_b\"")),
+            ]
+        );
+    }
+
+    #[test]
+    fn recipients_that_miss_the_deadline_are_marked() {
+        let now = Instant::now();
+        let mut gatherer = gatherer(now);
+        reply(&mut gatherer, "q_a", r#"{"price": 10}"#).unwrap();
+
+        assert!(gatherer.expire(now + Duration::from_secs(4)).is_empty());
+        let expired = gatherer.expire(now + Duration::from_secs(5));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].timed_out, vec![String::from("q_b")]);
+        assert_eq!(
+            serde_json::from_str::<Value>(&expired[0].response()).unwrap()["complete"],
+            Value::Bool(false)
+        );
+    }
+}
@@ -0,0 +1,712 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    aggregator::{Aggregate, Message},
+    completion::CompletionReason,
+    config::parse_duration,
+    function::{timestamp_header, AggregationError, AggregationFunction, Part},
+    groups::PerGroup,
+};
+
+/// How messages are bucketed into time windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    /// Consecutive windows of the given size that do not overlap, so every message falls into exactly one.
+    Tumbling { size: Duration },
+    /// Windows of the given size that start every `slide`, so a message falls into `size / slide` of them.
+    Sliding { size: Duration, slide: Duration },
+}
+
+impl FromStr for WindowKind {
+    type Err = String;
+
+    /// Parses `tumbling:<size>` or `sliding:<size>/<slide>`, such as `tumbling:1m` or `sliding:5m/1m`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, argument) = s.trim().split_once(':').ok_or_else(|| {
+            format!(
+                "Invalid window '{}', expected 'tumbling:<size>' or 'sliding:<size>/<slide>'",
+                s
+            )
+        })?;
+        let positive = |duration: &str| match parse_duration(duration)? {
+            duration if duration.as_millis() == 0 => {
+                Err(String::from("A window has to be at least 1ms long"))
+            }
+            duration => Ok(duration),
+        };
+
+        match kind.to_ascii_lowercase().as_str() {
+            "tumbling" => Ok(WindowKind::Tumbling {
+                size: positive(argument)?,
+            }),
+            "sliding" => {
+                let (size, slide) = argument.split_once('/').ok_or_else(|| {
+                    format!(
+                        "Invalid sliding window '{}', expected '<size>/<slide>'",
+                        argument
+                    )
+                })?;
+                let (size, slide) = (positive(size)?, positive(slide)?);
+                if slide > size {
+                    return Err(format!(
+                        "The slide of window '{}' is longer than its size, which would skip messages",
+                        s
+                    ));
+                }
+                Ok(WindowKind::Sliding { size, slide })
+            }
+            other => Err(format!(
+                "Unknown window '{}', expected 'tumbling' or 'sliding'",
+                other
+            )),
+        }
+    }
+}
+
+impl WindowKind {
+    /// The windows a message with the timestamp falls into, or `None` if one of them would start or end outside
+    /// the range of timestamps.
+    fn windows(&self, timestamp: i64) -> Option<Vec<TimeWindow>> {
+        let (size, slide) = match self {
+            WindowKind::Tumbling { size } => (millis(*size), millis(*size)),
+            WindowKind::Sliding { size, slide } => (millis(*size), millis(*slide)),
+        };
+
+        let mut windows = Vec::new();
+        let mut start = timestamp.checked_sub(timestamp.rem_euclid(slide))?;
+        loop {
+            let end = start.checked_add(size)?;
+            if end <= timestamp {
+                return Some(windows);
+            }
+            windows.push(TimeWindow { start, end });
+            start = start.checked_sub(slide)?;
+        }
+    }
+}
+
+/// Where the time a message is bucketed by is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeSource {
+    /// The time the message arrived at the aggregator.
+    Arrival,
+    /// A header with the time in milliseconds since the epoch, such as the time a booking was made.
+    Header(String),
+}
+
+impl FromStr for TimeSource {
+    type Err = String;
+
+    /// Parses `arrival` or `header:<name>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once(':') {
+            None if s.trim().eq_ignore_ascii_case("arrival") => Ok(TimeSource::Arrival),
+            Some((kind, name)) if kind.eq_ignore_ascii_case("header") && !name.is_empty() => {
+                Ok(TimeSource::Header(name.to_string()))
+            }
+            _ => Err(format!(
+                "Invalid time source '{}', expected 'arrival' or 'header:<name>'",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings of the windowed mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowSettings {
+    pub kind: WindowKind,
+    pub time: TimeSource,
+    /// How far the watermark trails the latest time seen, which is how out of order messages may arrive before
+    /// their window closes.
+    pub watermark_delay: Duration,
+    /// How long a window is kept after it closed, so that messages arriving later still update its aggregate.
+    pub allowed_lateness: Duration,
+    /// How far ahead of the wall clock the time of a message may be. A message from further ahead is refused, as it
+    /// would move the watermark past every window to come, and have all later messages refused as too late.
+    pub max_clock_skew: Duration,
+}
+
+/// A time window, from `start` up to but excluding `end`, both in milliseconds since the epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimeWindow {
+    pub start: i64,
+    pub end: i64,
+}
+
+/// Why a message cannot be added to any window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowError {
+    MissingTimestamp {
+        header: String,
+    },
+    /// Every window of the message is past its allowed lateness.
+    TooLate {
+        timestamp: i64,
+        watermark: i64,
+    },
+    /// The time of the message is further ahead of the wall clock than the allowed clock skew.
+    InFuture {
+        timestamp: i64,
+        limit: i64,
+    },
+    /// The windows of the message would start or end outside the range of timestamps.
+    OutOfRange {
+        timestamp: i64,
+    },
+    Aggregation(AggregationError),
+}
+
+impl WindowError {
+    /// A short machine readable identifier of the error.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            WindowError::MissingTimestamp { .. } => "missing_timestamp",
+            WindowError::TooLate { .. } => "too_late",
+            WindowError::InFuture { .. } => "timestamp_in_future",
+            WindowError::OutOfRange { .. } => "timestamp_out_of_range",
+            WindowError::Aggregation(e) => e.reason(),
+        }
+    }
+}
+
+impl fmt::Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowError::MissingTimestamp { header } => {
+                write!(f, "header '{}' is not an integer timestamp", header)
+            }
+            WindowError::TooLate {
+                timestamp,
+                watermark,
+            } => write!(
+                f,
+                "timestamp {} is past the allowed lateness of every window, with the watermark at {}",
+                timestamp, watermark
+            ),
+            WindowError::InFuture { timestamp, limit } => write!(
+                f,
+                "timestamp {} is ahead of the wall clock by more than the allowed clock skew, up to {}",
+                timestamp, limit
+            ),
+            WindowError::OutOfRange { timestamp } => {
+                write!(f, "timestamp {} has no windows in range", timestamp)
+            }
+            WindowError::Aggregation(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for WindowError {}
+
+/// The messages of a correlation key that fell into a time window.
+#[derive(Debug)]
+struct Window {
+    group: Option<String>,
+    function: Arc<dyn AggregationFunction>,
+    parts: Vec<Part>,
+    /// Delivery tags of the parts no emitted aggregate carried yet, or whose aggregate failed to publish.
+    unsettled: Vec<u64>,
+    /// Delivery tags carried by emitted aggregates whose publish has not been confirmed or failed yet.
+    in_flight: Vec<u64>,
+    closed: bool,
+    /// Why the aggregate was emitted the last time, if its publish failed so that it is emitted again.
+    retry: Option<CompletionReason>,
+}
+
+/// Buckets messages into time windows per correlation key, and emits the aggregate of each window once the
+/// watermark passes its end.
+///
+/// The watermark trails the latest time seen by the watermark delay. While no messages arrive, it keeps
+/// advancing with the wall clock, so that the last windows close too. A closed window is kept for the allowed
+/// lateness, and every message that arrives for it in the meantime emits its aggregate again, updated.
+///
+/// An aggregate carries the delivery tags of the parts it is the first to include, and is reported back through
+/// [`Windows::published`]. As a delivery falls into every window that overlaps its time, it is only settled once
+/// the aggregates of all of them were confirmed. A window whose aggregate failed to publish emits it again on the
+/// next [`Windows::advance`], and is kept until then, even past its allowed lateness.
+#[derive(Debug)]
+pub struct Windows {
+    settings: WindowSettings,
+    functions: PerGroup<Arc<dyn AggregationFunction>>,
+    windows: HashMap<(String, TimeWindow), Window>,
+    /// How many of the windows every delivery fell into have not had an aggregate including it confirmed yet.
+    pending: HashMap<u64, usize>,
+    /// The latest time seen, along with the wall clock time the latest message arrived.
+    latest: Option<(i64, i64)>,
+    watermark: i64,
+}
+
+impl Windows {
+    pub fn new(
+        settings: WindowSettings,
+        functions: PerGroup<Arc<dyn AggregationFunction>>,
+    ) -> Windows {
+        Windows {
+            settings,
+            functions,
+            windows: HashMap::new(),
+            pending: HashMap::new(),
+            latest: None,
+            watermark: i64::MIN,
+        }
+    }
+
+    /// Adds the message to the windows its time falls into, where `now` is the wall clock time in milliseconds
+    /// since the epoch, and returns the aggregates of the windows that closed or were updated by a late message.
+    ///
+    /// A message is refused, leaving the windows as they were, if it has no time, if its time is too far ahead of
+    /// the wall clock or has no windows in range, if it is too late for all its windows, or if the function of one of
+    /// its windows cannot aggregate it.
+    pub fn push(&mut self, message: Message, now: i64) -> Result<Vec<Aggregate>, WindowError> {
+        let timestamp = match &self.settings.time {
+            TimeSource::Arrival => now,
+            TimeSource::Header(name) => timestamp_header(message.headers.as_ref(), name)
+                .ok_or_else(|| WindowError::MissingTimestamp {
+                    header: name.clone(),
+                })?,
+        };
+        let limit = now.saturating_add(millis(self.settings.max_clock_skew));
+        if timestamp > limit {
+            return Err(WindowError::InFuture { timestamp, limit });
+        }
+
+        // Windows past their allowed lateness have been forgotten already
+        let lateness = millis(self.settings.allowed_lateness);
+        let windows: Vec<TimeWindow> = self
+            .settings
+            .kind
+            .windows(timestamp)
+            .ok_or(WindowError::OutOfRange { timestamp })?
+            .into_iter()
+            .filter(|window| window.end.saturating_add(lateness) > self.watermark)
+            .collect();
+        if windows.is_empty() {
+            return Err(WindowError::TooLate {
+                timestamp,
+                watermark: self.watermark,
+            });
+        }
+
+        let part = Part {
+            payload: message.payload,
+            headers: message.headers,
+        };
+        let group_function = Arc::clone(self.functions.for_group(message.group.as_deref()));
+        for window in &windows {
+            let key = (message.correlation_key.clone(), *window);
+            match self.windows.get(&key) {
+                Some(existing) => existing.function.check(&part),
+                None => group_function.check(&part),
+            }
+            .map_err(WindowError::Aggregation)?;
+        }
+
+        let mut aggregates = Vec::new();
+        self.pending.insert(message.delivery_tag, windows.len());
+        for window in windows {
+            let key = (message.correlation_key.clone(), window);
+            let entry = self.windows.entry(key.clone()).or_insert_with(|| Window {
+                group: message.group.clone(),
+                function: Arc::clone(&group_function),
+                parts: Vec::new(),
+                unsettled: Vec::new(),
+                in_flight: Vec::new(),
+                closed: false,
+                retry: None,
+            });
+            entry.parts.push(part.clone());
+            entry.unsettled.push(message.delivery_tag);
+            if entry.closed {
+                aggregates.extend(self.emit(&key, CompletionReason::LateArrival));
+            }
+        }
+
+        let latest = self
+            .latest
+            .map_or(timestamp, |(latest, _)| latest.max(timestamp));
+        self.latest = Some((latest, now));
+        aggregates.extend(self.advance(now));
+        Ok(aggregates)
+    }
+
+    /// Moves the watermark on to the wall clock time `now`, and returns the aggregates of the windows it closed,
+    /// along with those whose publish failed.
+    pub fn advance(&mut self, now: i64) -> Vec<Aggregate> {
+        if let Some((latest, arrived_at)) = self.latest {
+            let idle = now.saturating_sub(arrived_at).max(0);
+            let watermark = latest
+                .saturating_add(idle)
+                .saturating_sub(millis(self.settings.watermark_delay));
+            self.watermark = self.watermark.max(watermark);
+        }
+
+        let mut emitting: Vec<((String, TimeWindow), CompletionReason)> = self
+            .windows
+            .iter()
+            .filter_map(|(key, window)| match window.retry {
+                Some(reason) => Some((key.clone(), reason)),
+                None if !window.closed && key.1.end <= self.watermark => {
+                    Some((key.clone(), CompletionReason::WindowClosed))
+                }
+                None => None,
+            })
+            .collect();
+        emitting
+            .sort_by_key(|((correlation_key, window), _)| (window.end, correlation_key.clone()));
+        let aggregates = emitting
+            .into_iter()
+            .filter_map(|(key, reason)| self.emit(&key, reason))
+            .collect();
+
+        // Windows whose deliveries are not settled yet are kept, so the aggregate can be published again
+        let lateness = millis(self.settings.allowed_lateness);
+        let watermark = self.watermark;
+        self.windows.retain(|(_, time_window), window| {
+            time_window.end.saturating_add(lateness) > watermark
+                || !window.unsettled.is_empty()
+                || !window.in_flight.is_empty()
+        });
+        aggregates
+    }
+
+    /// Records whether the publish of an aggregate emitted by these windows was confirmed, and returns the delivery
+    /// tags that are settled by it, as every window they fell into has had its aggregate confirmed.
+    ///
+    /// A failed publish settles nothing: the deliveries stay with the window, which emits its aggregate again. They
+    /// are not requeued, as the windows they fell into too would count them a second time.
+    pub fn published(&mut self, aggregate: &Aggregate, confirmed: bool) -> Vec<u64> {
+        let window = aggregate.window.and_then(|window| {
+            self.windows
+                .get_mut(&(aggregate.correlation_key.clone(), window))
+        });
+        let window = match window {
+            Some(window) => window,
+            None => return Vec::new(),
+        };
+        window
+            .in_flight
+            .retain(|delivery_tag| !aggregate.delivery_tags.contains(delivery_tag));
+        if !confirmed {
+            window.unsettled.extend(&aggregate.delivery_tags);
+            window.retry = Some(aggregate.reason);
+            return Vec::new();
+        }
+
+        let mut settled = Vec::new();
+        for &delivery_tag in &aggregate.delivery_tags {
+            if let Some(remaining) = self.pending.get_mut(&delivery_tag) {
+                *remaining -= 1;
+                if *remaining == 0 {
+                    self.pending.remove(&delivery_tag);
+                    settled.push(delivery_tag);
+                }
+            }
+        }
+        settled
+    }
+
+    /// The current watermark, in milliseconds since the epoch.
+    pub fn watermark(&self) -> i64 {
+        self.watermark
+    }
+
+    /// How many windows are open or kept for late messages.
+    pub fn open_windows(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// Emits the aggregate of a window, carrying the delivery tags of the parts no aggregate in flight or confirmed
+    /// includes yet.
+    fn emit(&mut self, key: &(String, TimeWindow), reason: CompletionReason) -> Option<Aggregate> {
+        let window = self.windows.get_mut(key)?;
+        window.closed = true;
+        window.retry = None;
+
+        let delivery_tags: Vec<u64> = window.unsettled.drain(..).collect();
+        window.in_flight.extend(&delivery_tags);
+
+        Some(Aggregate {
+            correlation_key: key.0.clone(),
+            group: window.group.clone(),
+            received: window.parts.len(),
+            payload: window.function.aggregate(&window.parts),
+            content_type: window.function.content_type().map(String::from),
+            reason,
+            delivery_tags,
+            window: Some(key.1),
+        })
+    }
+}
+
+/// The wall clock time in milliseconds since the epoch.
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, millis)
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use lapin::types::{AMQPValue, FieldTable};
+
+    use super::*;
+    use crate::function;
+
+    const MINUTE: i64 = 60_000;
+
+    fn windows(kind: &str, watermark_delay: Duration, allowed_lateness: Duration) -> Windows {
+        Windows::new(
+            WindowSettings {
+                kind: kind.parse().unwrap(),
+                time: "header:timestamp".parse().unwrap(),
+                watermark_delay,
+                allowed_lateness,
+                // The tests push messages from hours ahead of a wall clock that stands still
+                max_clock_skew: Duration::from_secs(24 * 60 * 60),
+            },
+            PerGroup::new(function::from_name("count").unwrap()),
+        )
+    }
+
+    fn message(delivery_tag: u64, timestamp: i64) -> Message {
+        let mut headers = FieldTable::default();
+        headers.insert("timestamp".into(), AMQPValue::LongLongInt(timestamp));
+        Message {
+            correlation_key: "bookings".to_string(),
+            group: None,
+            headers: Some(headers),
+            payload: String::new(),
+            delivery_tag,
+        }
+    }
+
+    #[test]
+    fn windows_parse_from_their_description() {
+        assert_eq!(
+            "tumbling:1m".parse(),
+            Ok(WindowKind::Tumbling {
+                size: Duration::from_secs(60)
+            })
+        );
+        assert_eq!(
+            "sliding:5m/1m".parse(),
+            Ok(WindowKind::Sliding {
+                size: Duration::from_secs(300),
+                slide: Duration::from_secs(60)
+            })
+        );
+        assert!("tumbling:0s".parse::<WindowKind>().is_err());
+        assert!("sliding:1m/5m".parse::<WindowKind>().is_err());
+        assert!("sliding:5m".parse::<WindowKind>().is_err());
+        assert_eq!("arrival".parse(), Ok(TimeSource::Arrival));
+        assert_eq!(
+            "header:booked_at".parse(),
+            Ok(TimeSource::Header("booked_at".to_string()))
+        );
+        assert!("header:".parse::<TimeSource>().is_err());
+    }
+
+    #[test]
+    fn sliding_windows_overlap() {
+        let kind: WindowKind = "sliding:3m/1m".parse().unwrap();
+        let starts: Vec<i64> = kind
+            .windows(5 * MINUTE + 1)
+            .unwrap()
+            .iter()
+            .map(|window| window.start)
+            .collect();
+        assert_eq!(starts, vec![5 * MINUTE, 4 * MINUTE, 3 * MINUTE]);
+    }
+
+    #[test]
+    fn tumbling_windows_close_once_the_watermark_passes_them() {
+        let mut windows = windows("tumbling:1m", Duration::ZERO, Duration::ZERO);
+
+        assert!(windows.push(message(1, 10), 10).unwrap().is_empty());
+        assert!(windows.push(message(2, MINUTE - 1), 20).unwrap().is_empty());
+
+        let closed = windows.push(message(3, MINUTE + 5), 30).unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].payload, "2");
+        assert_eq!(closed[0].reason, CompletionReason::WindowClosed);
+        assert_eq!(
+            closed[0].window,
+            Some(TimeWindow {
+                start: 0,
+                end: MINUTE
+            })
+        );
+        assert_eq!(closed[0].delivery_tags, vec![1, 2]);
+        assert_eq!(publish(&mut windows, &closed, true), vec![vec![1, 2]]);
+
+        // Without further messages, the watermark advances with the wall clock
+        assert!(windows.advance(30 + MINUTE - 10).is_empty());
+        let closed = windows.advance(30 + MINUTE - 5);
+        assert_eq!(closed[0].payload, "1");
+        assert_eq!(publish(&mut windows, &closed, true), vec![vec![3]]);
+        // Confirmed windows are dropped on the next advance
+        assert!(windows.advance(30 + MINUTE - 5).is_empty());
+        assert_eq!(windows.open_windows(), 0);
+    }
+
+    #[test]
+    fn late_messages_update_their_window_within_the_allowed_lateness() {
+        let mut windows = windows(
+            "tumbling:1m",
+            Duration::from_secs(10),
+            Duration::from_secs(30),
+        );
+
+        windows.push(message(1, 10), 0).unwrap();
+        // Out of order by less than the watermark delay, so the window is still open
+        windows.push(message(2, MINUTE + 5_000), 0).unwrap();
+        windows.push(message(3, 20), 0).unwrap();
+
+        let closed = windows.push(message(4, MINUTE + 10_000), 0).unwrap();
+        assert_eq!(closed[0].payload, "2");
+        assert_eq!(closed[0].delivery_tags, vec![1, 3]);
+
+        let updated = windows.push(message(5, 30), 0).unwrap();
+        assert_eq!(updated[0].payload, "3");
+        assert_eq!(updated[0].reason, CompletionReason::LateArrival);
+        assert_eq!(updated[0].delivery_tags, vec![5]);
+
+        windows.push(message(6, MINUTE + 40_000), 0).unwrap();
+        assert_eq!(
+            windows.push(message(7, 40), 0).unwrap_err().reason(),
+            "too_late"
+        );
+    }
+
+    /// Reports the publish of every aggregate, returning the delivery tags each of them settled.
+    fn publish(windows: &mut Windows, aggregates: &[Aggregate], confirmed: bool) -> Vec<Vec<u64>> {
+        aggregates
+            .iter()
+            .map(|aggregate| windows.published(aggregate, confirmed))
+            .collect()
+    }
+
+    #[test]
+    fn deliveries_are_settled_once_all_their_windows_are_confirmed() {
+        let mut windows = windows("sliding:2m/1m", Duration::ZERO, Duration::ZERO);
+
+        windows.push(message(1, MINUTE + 1), 0).unwrap();
+        let closed = windows.push(message(2, 2 * MINUTE + 1), 0).unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].delivery_tags, vec![1]);
+        // Delivery 1 also fell into the window up to 3m, which is still open
+        assert_eq!(
+            publish(&mut windows, &closed, true),
+            vec![Vec::<u64>::new()]
+        );
+
+        let closed = windows.push(message(3, 3 * MINUTE + 1), 0).unwrap();
+        assert_eq!(closed[0].payload, "2");
+        assert_eq!(closed[0].delivery_tags, vec![1, 2]);
+        assert_eq!(publish(&mut windows, &closed, true), vec![vec![1]]);
+
+        assert_eq!(
+            windows.push(
+                Message {
+                    headers: None,
+                    ..message(4, 0)
+                },
+                0
+            ),
+            Err(WindowError::MissingTimestamp {
+                header: "timestamp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn failed_publishes_are_emitted_again_without_counting_twice() {
+        let mut windows = windows("sliding:2m/1m", Duration::ZERO, Duration::ZERO);
+
+        windows.push(message(1, MINUTE + 1), 0).unwrap();
+        let failed = windows.push(message(2, 2 * MINUTE + 1), 0).unwrap();
+        assert_eq!(failed[0].window.unwrap().end, 2 * MINUTE);
+        assert_eq!(
+            publish(&mut windows, &failed, false),
+            vec![Vec::<u64>::new()]
+        );
+
+        // The overlapping window is confirmed meanwhile, but delivery 1 waits for the failed one
+        let closed = windows.push(message(3, 3 * MINUTE + 1), 0).unwrap();
+        let retried: Vec<&Aggregate> = closed
+            .iter()
+            .filter(|aggregate| aggregate.window == failed[0].window)
+            .collect();
+        let overlapping: Vec<Aggregate> = closed
+            .iter()
+            .filter(|aggregate| aggregate.window != failed[0].window)
+            .cloned()
+            .collect();
+        assert_eq!(retried.len(), 1);
+        assert_eq!(retried[0].payload, "1");
+        assert_eq!(retried[0].delivery_tags, vec![1]);
+        assert_eq!(overlapping[0].payload, "2");
+        assert_eq!(
+            publish(&mut windows, &overlapping, true),
+            vec![Vec::<u64>::new()]
+        );
+
+        // Kept past its allowed lateness until its aggregate is confirmed
+        assert_eq!(windows.published(retried[0], true), vec![1]);
+        windows.advance(0);
+        assert!(windows
+            .windows
+            .keys()
+            .all(|(_, window)| window.end > 2 * MINUTE));
+    }
+
+    #[test]
+    fn timestamps_far_ahead_of_the_wall_clock_are_refused() {
+        let mut windows = windows("tumbling:1m", Duration::ZERO, Duration::ZERO);
+        windows.settings.max_clock_skew = Duration::from_secs(60);
+        let now = 100 * MINUTE;
+
+        assert_eq!(
+            windows.push(message(1, now + 2 * MINUTE), now),
+            Err(WindowError::InFuture {
+                timestamp: now + 2 * MINUTE,
+                limit: now + MINUTE
+            })
+        );
+        assert_eq!(windows.watermark(), i64::MIN);
+
+        // The refused message left the watermark alone, so earlier timestamps are still on time
+        windows.push(message(2, now), now).unwrap();
+        windows.push(message(3, now + MINUTE), now).unwrap();
+        assert_eq!(windows.watermark(), now + MINUTE);
+    }
+
+    #[test]
+    fn timestamps_without_windows_in_range_are_refused() {
+        let kind: WindowKind = "sliding:2m/1m".parse().unwrap();
+        assert_eq!(kind.windows(i64::MAX), None);
+        assert_eq!(kind.windows(i64::MIN), None);
+
+        let mut windows = windows("tumbling:1m", Duration::ZERO, Duration::ZERO);
+        windows.settings.max_clock_skew = Duration::MAX;
+        assert_eq!(
+            windows.push(message(1, i64::MAX), 0).unwrap_err().reason(),
+            "timestamp_out_of_range"
+        );
+        assert_eq!(
+            windows.push(message(2, i64::MIN), 0).unwrap_err().reason(),
+            "timestamp_out_of_range"
+        );
+    }
+}
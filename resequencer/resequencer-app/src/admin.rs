@@ -0,0 +1,161 @@
+//! The admin endpoint, which lets operators inspect the open sequences and close those that are stuck.
+
+use std::{cmp::Reverse, sync::Arc, time::Instant};
+
+use lapin::Channel;
+use resequencer_app::{config::Config, shards::Shards};
+use resequencer_core::resequencer::{OpenSequence, SequenceDump};
+use rocket::{delete, get, post, routes, serde::json::Json, State};
+use serde::Serialize;
+
+use crate::{print_release, publish_incomplete, publish_release, settle_release};
+
+/// What the routes of the admin endpoint act on.
+pub struct Admin {
+    pub shards: Arc<Shards>,
+    pub channel: Channel,
+    pub config: Arc<Config>,
+}
+
+/// Outcome of flushing a sequence.
+#[derive(Serialize)]
+struct Flushed {
+    sequence_id: String,
+    /// The `first_sequence` and `last_sequence` of every release that was published.
+    released: Vec<(u64, u64)>,
+    missing: Vec<u64>,
+    /// Whether the broker confirmed every release.
+    confirmed: bool,
+}
+
+/// Outcome of discarding a sequence.
+#[derive(Serialize)]
+struct Discarded {
+    sequence_id: String,
+    received: Vec<u64>,
+    missing: Vec<u64>,
+    /// Whether the broker confirmed the report of the discarded sequence.
+    confirmed: bool,
+}
+
+/// Serves the admin endpoint until the process stops.
+pub async fn serve(admin: Admin) {
+    let port = match admin.config.admin_port {
+        Some(port) => port,
+        None => return,
+    };
+
+    // The process is stopped by the signals as before, rather than only shutting down the admin endpoint
+    let figment = rocket::Config::figment()
+        .merge(("address", admin.config.admin_address))
+        .merge(("port", port))
+        .merge(("shutdown.ctrlc", false))
+        .merge(("shutdown.signals", Vec::<String>::new()));
+
+    let result = rocket::custom(figment)
+        .manage(admin)
+        .mount(
+            "/",
+            routes![
+                list_sequences,
+                get_sequence,
+                flush_sequence,
+                discard_sequence
+            ],
+        )
+        .launch()
+        .await;
+    if let Err(e) = result {
+        println!("[Error] Admin endpoint stopped: {}", e);
+    }
+}
+
+/// Lists every open sequence, oldest first.
+#[get("/sequences")]
+async fn list_sequences(admin: &State<Admin>) -> Json<Vec<OpenSequence>> {
+    let now = Instant::now();
+    let mut open = Vec::new();
+    for shard in admin.shards.iter() {
+        open.extend(shard.lock().await.open_sequences(now));
+    }
+    open.sort_by_key(|sequence| Reverse(sequence.open_for_ms));
+    Json(open)
+}
+
+/// Dumps an open sequence along with the parts it holds, whose payloads are base64 encoded.
+#[get("/sequences/<sequence_id>")]
+async fn get_sequence(admin: &State<Admin>, sequence_id: &str) -> Option<Json<SequenceDump>> {
+    let shard = admin.shards.for_sequence(sequence_id);
+    let dump = shard.lock().await.dump(sequence_id, Instant::now());
+    dump.map(Json)
+}
+
+/// Publishes the parts an open sequence holds without waiting for those that are missing, and closes it.
+#[post("/sequences/<sequence_id>/flush")]
+async fn flush_sequence(admin: &State<Admin>, sequence_id: &str) -> Option<Json<Flushed>> {
+    let shard = admin.shards.for_sequence(sequence_id);
+
+    // Published while holding the lock, as the releases of the consumer are
+    let (missing, published) = {
+        let mut state = shard.lock().await;
+        let missing = state.dump(sequence_id, Instant::now())?.sequence.missing;
+        let mut published = Vec::new();
+        let releases = state.flush(sequence_id)?.unwrap_or_else(|e| {
+            println!(
+                "[Error] Could not record the removal of sequence_id '{}' in the state store: {}",
+                sequence_id, e
+            );
+            Vec::new()
+        });
+        for release in releases {
+            print_release(&release);
+            let confirm = publish_release(&admin.channel, &admin.config, &release).await;
+            published.push((release, confirm));
+        }
+        (missing, published)
+    };
+    println!(
+        "[Warning] Flushed sequence_id '{}' on request, missing: {:?}",
+        sequence_id, missing
+    );
+
+    let mut released = Vec::new();
+    let mut confirmed = true;
+    for (release, confirm) in published {
+        released.push((release.first_sequence, release.last_sequence()));
+        confirmed &= settle_release(&admin.channel, shard, confirm, release).await;
+    }
+
+    Some(Json(Flushed {
+        sequence_id: sequence_id.to_string(),
+        released,
+        missing,
+        confirmed,
+    }))
+}
+
+/// Discards an open sequence without publishing its parts, and reports it like an evicted sequence.
+#[delete("/sequences/<sequence_id>")]
+async fn discard_sequence(admin: &State<Admin>, sequence_id: &str) -> Option<Json<Discarded>> {
+    let shard = admin.shards.for_sequence(sequence_id);
+    let discarded = shard.lock().await.discard(sequence_id, Instant::now())?;
+
+    let response = Discarded {
+        sequence_id: discarded.sequence_id.clone(),
+        received: discarded.received.clone(),
+        missing: discarded.missing.clone(),
+        confirmed: false,
+    };
+    let confirmed = publish_incomplete(
+        &admin.channel,
+        shard,
+        &admin.config.dlx_exchange,
+        vec![discarded],
+    )
+    .await;
+
+    Some(Json(Discarded {
+        confirmed,
+        ..response
+    }))
+}
@@ -0,0 +1,106 @@
+//! Pushes interleaved sequences through the resequencer, without a broker, and reports the throughput.
+//!
+//! Usage: `cargo run --release --bin resequencer-bench -- [sequences] [parts] [shards] [mode]`
+
+use std::{env, thread, time::Instant};
+
+use resequencer_app::shards::shard_of;
+use resequencer_core::resequencer::{
+    DuplicatePolicy, EvictionPolicy, ReleaseMode, Resequencer, Sequence,
+};
+
+const PAYLOAD_SIZE: usize = 64;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let num_sequences: usize = arg(&args, 0, 10_000);
+    let num_parts: u64 = arg(&args, 1, 10);
+    let num_shards: usize = arg(&args, 2, 1).max(1);
+    let mode: ReleaseMode = arg(&args, 3, ReleaseMode::Batch);
+
+    // Every part of every sequence, shuffled so that sequences interleave and arrive out of order
+    let mut arrivals: Vec<(String, u64)> = (0..num_sequences)
+        .flat_map(|id| (1..=num_parts).map(move |sequence| (format!("sequence-{}", id), sequence)))
+        .collect();
+    shuffle(&mut arrivals);
+    let total = arrivals.len();
+
+    // Each shard is driven by its own thread, as unrelated sequences are in the resequencer-app
+    let mut per_shard: Vec<Vec<(String, u64)>> = (0..num_shards).map(|_| Vec::new()).collect();
+    for (sequence_id, sequence) in arrivals {
+        per_shard[shard_of(&sequence_id, num_shards)].push((sequence_id, sequence));
+    }
+
+    println!(
+        "[Information] Pushing {} sequences of {} parts ({} messages) through {} shard(s) in {:?} mode",
+        num_sequences, num_parts, total, num_shards, mode
+    );
+
+    let started = Instant::now();
+    let handles: Vec<_> = per_shard
+        .into_iter()
+        .map(|arrivals| thread::spawn(move || push_all(arrivals, num_parts, mode)))
+        .collect();
+    let completed: usize = handles
+        .into_iter()
+        .map(|handle| handle.join().expect("[Error] Shard thread panicked"))
+        .sum();
+    let elapsed = started.elapsed();
+
+    println!(
+        "[Information] Completed {} of {} sequences in {:.3}s, {:.0} messages/s",
+        completed,
+        num_sequences,
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64()
+    );
+}
+
+/// Pushes the arrivals through a fresh resequencer, returning how many sequences were completed.
+fn push_all(arrivals: Vec<(String, u64)>, num_parts: u64, mode: ReleaseMode) -> usize {
+    let eviction = EvictionPolicy {
+        sequence_timeout: None,
+        max_open_sequences: None,
+    };
+    let mut resequencer = Resequencer::new(mode, eviction, DuplicatePolicy::KeepFirst);
+    let mut completed = 0;
+
+    for (delivery_tag, (sequence_id, sequence)) in arrivals.into_iter().enumerate() {
+        let new_sequence = Sequence::new(
+            vec![0; PAYLOAD_SIZE],
+            num_parts,
+            sequence,
+            sequence_id,
+            Some(delivery_tag as u64),
+        );
+        let pushed = resequencer
+            .push_sequence(new_sequence)
+            .expect("[Error] Benchmark part was rejected");
+        if pushed.release.is_some_and(|release| release.complete) {
+            completed += 1;
+        }
+    }
+
+    completed
+}
+
+/// Shuffles deterministically with a xorshift generator, so runs are comparable.
+fn shuffle<T>(items: &mut [T]) {
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
+
+fn arg<T: std::str::FromStr>(args: &[String], index: usize, default: T) -> T {
+    match args.get(index) {
+        Some(value) => match value.parse() {
+            Ok(parsed) => parsed,
+            Err(_) => panic!("[Critical] Invalid argument '{}'", value),
+        },
+        None => default,
+    }
+}
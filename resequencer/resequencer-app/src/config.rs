@@ -0,0 +1,115 @@
+use std::{
+    env,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use resequencer_core::{
+    joiner::{self, Joiner},
+    resequencer::{DuplicatePolicy, EvictionPolicy, ReleaseMode},
+};
+
+/// Settings of the resequencer, read from environment variables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mode: ReleaseMode,
+    pub eviction: EvictionPolicy,
+    pub duplicates: DuplicatePolicy,
+    /// Largest `num_sequences` a message may carry, as a slot is held for every part of an open sequence.
+    pub max_num_sequences: u64,
+    /// Exchange that receives incomplete sequence reports and malformed messages.
+    pub dlx_exchange: String,
+    /// Exchange that receives the resequenced payloads. An empty name is the default exchange.
+    pub output_exchange: String,
+    pub output_routing_key: String,
+    /// Joins the payloads of a release into the published message.
+    pub joiner: Arc<dyn Joiner>,
+    /// File the state is persisted to. `None` keeps the state in memory only.
+    pub state_file: Option<PathBuf>,
+    /// How many shards the state is split into, so unrelated sequences are resequenced in parallel.
+    pub shards: usize,
+    /// How many unacknowledged messages the broker delivers at once. 0 is unlimited.
+    pub prefetch: u16,
+    /// Tag the input queue is consumed with, naming the resequencer in the broker's consumer list.
+    pub consumer_tag: String,
+    /// Address the admin endpoint listens on.
+    pub admin_address: IpAddr,
+    /// Port the admin endpoint listens on. `None` disables it.
+    pub admin_port: Option<u16>,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        // A value of 0 disables the respective limit
+        let timeout_secs: u64 = env_or("RESEQUENCER_SEQUENCE_TIMEOUT_SECS", 300);
+        let max_open_sequences: usize = env_or("RESEQUENCER_MAX_OPEN_SEQUENCES", 10_000);
+        let max_num_sequences: u64 = env_or("RESEQUENCER_MAX_NUM_SEQUENCES", 10_000);
+        if max_num_sequences == 0 {
+            panic!(
+                "[Critical] Environment variable `RESEQUENCER_MAX_NUM_SEQUENCES` has to be at least 1"
+            );
+        }
+        let shards: usize = env_or("RESEQUENCER_SHARDS", 4);
+        if shards == 0 {
+            panic!("[Critical] Environment variable `RESEQUENCER_SHARDS` has to be at least 1");
+        }
+        // An empty path disables persistence
+        let state_file: String = env_or(
+            "RESEQUENCER_STATE_FILE",
+            String::from("resequencer-state.jsonl"),
+        );
+        // A port of 0 disables the admin endpoint
+        let admin_port: u16 = env_or("RESEQUENCER_ADMIN_PORT", 8081);
+
+        Config {
+            mode: env_or("RESEQUENCER_MODE", ReleaseMode::Batch),
+            eviction: EvictionPolicy {
+                sequence_timeout: (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs)),
+                max_open_sequences: (max_open_sequences > 0).then_some(max_open_sequences),
+            },
+            duplicates: env_or("RESEQUENCER_DUPLICATE_POLICY", DuplicatePolicy::KeepFirst),
+            max_num_sequences,
+            dlx_exchange: env_or("RESEQUENCER_DLX", String::from("e_resequencer_dlx")),
+            output_exchange: env_or("RESEQUENCER_OUTPUT_EXCHANGE", String::from("e_resequencer")),
+            output_routing_key: env_or(
+                "RESEQUENCER_OUTPUT_ROUTING_KEY",
+                String::from("resequencer.output"),
+            ),
+            joiner: match joiner::from_name(&env_or("RESEQUENCER_JOINER", String::from("concat"))) {
+                Ok(joiner) => joiner,
+                Err(e) => panic!(
+                    "[Critical] Environment variable `RESEQUENCER_JOINER` is invalid: {}",
+                    e
+                ),
+            },
+            state_file: (!state_file.is_empty()).then(|| PathBuf::from(state_file)),
+            shards,
+            prefetch: env_or("RESEQUENCER_PREFETCH", 1000),
+            consumer_tag: env_or("RESEQUENCER_CONSUMER_TAG", String::from("resequencer")),
+            admin_address: env_or("RESEQUENCER_ADMIN_ADDRESS", IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            admin_port: (admin_port > 0).then_some(admin_port),
+        }
+    }
+}
+
+/// Reads and parses the environment variable `name`, falling back to `default` when it is unassigned.
+fn env_or<T>(name: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) => match value.parse::<T>() {
+            Ok(parsed) => parsed,
+            Err(e) => panic!(
+                "[Critical] Environment variable `{}` is invalid: {}",
+                name, e
+            ),
+        },
+        Err(_) => default,
+    }
+}
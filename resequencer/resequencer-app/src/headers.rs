@@ -0,0 +1,333 @@
+use std::fmt;
+
+use lapin::types::{AMQPType, AMQPValue, FieldTable};
+
+/// The headers every message consumed by the resequencer has to carry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceHeaders {
+    pub sequence_id: String,
+    /// One based position of the message within its sequence.
+    pub sequence: u64,
+    pub num_sequences: u64,
+}
+
+/// Why the headers of a message could not be turned into [`SequenceHeaders`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    Missing(&'static str),
+    WrongType {
+        header: &'static str,
+        found: AMQPType,
+    },
+    InvalidUtf8(&'static str),
+    Negative {
+        header: &'static str,
+        value: i64,
+    },
+    EmptySequenceId,
+    ZeroNumSequences,
+    ZeroSequence,
+    SequenceOutOfRange {
+        sequence: u64,
+        num_sequences: u64,
+    },
+    TooManySequences {
+        num_sequences: u64,
+        max_num_sequences: u64,
+    },
+}
+
+impl HeaderError {
+    /// A short machine readable identifier of the error, attached to dead-lettered messages.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            HeaderError::Missing(_) => "missing_header",
+            HeaderError::WrongType { .. } => "wrong_header_type",
+            HeaderError::InvalidUtf8(_) => "invalid_utf8_header",
+            HeaderError::Negative { .. } => "negative_header",
+            HeaderError::EmptySequenceId => "empty_sequence_id",
+            HeaderError::ZeroNumSequences => "zero_num_sequences",
+            HeaderError::ZeroSequence => "zero_sequence",
+            HeaderError::SequenceOutOfRange { .. } => "sequence_out_of_range",
+            HeaderError::TooManySequences { .. } => "num_sequences_too_large",
+        }
+    }
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::Missing(header) => write!(f, "header '{}' is missing", header),
+            HeaderError::WrongType { header, found } => {
+                write!(f, "header '{}' has the unsupported type {}", header, found)
+            }
+            HeaderError::InvalidUtf8(header) => write!(f, "header '{}' is not valid UTF-8", header),
+            HeaderError::Negative { header, value } => {
+                write!(f, "header '{}' is negative: {}", header, value)
+            }
+            HeaderError::EmptySequenceId => write!(f, "header 'sequence_id' is empty"),
+            HeaderError::ZeroNumSequences => write!(f, "header 'num_sequences' is 0"),
+            HeaderError::ZeroSequence => write!(f, "header 'sequence' is 0, sequences start at 1"),
+            HeaderError::SequenceOutOfRange {
+                sequence,
+                num_sequences,
+            } => write!(
+                f,
+                "header 'sequence' is {}, which exceeds 'num_sequences' of {}",
+                sequence, num_sequences
+            ),
+            HeaderError::TooManySequences {
+                num_sequences,
+                max_num_sequences,
+            } => write!(
+                f,
+                "header 'num_sequences' is {}, which exceeds the maximum of {}",
+                num_sequences, max_num_sequences
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+impl SequenceHeaders {
+    /// Parses and validates the sequence headers of a message.
+    ///
+    /// `sequence_id` may be a LongString or a ShortString, while `sequence` and `num_sequences` may be any
+    /// non-negative integer type. Publishers such as the management UI default numbers to LongLongInt.
+    ///
+    /// `num_sequences` may not exceed `max_num_sequences`, as a slot is held for every part of an open sequence.
+    pub fn parse(
+        headers: Option<&FieldTable>,
+        max_num_sequences: u64,
+    ) -> Result<SequenceHeaders, HeaderError> {
+        let empty = FieldTable::default();
+        let headers = headers.unwrap_or(&empty);
+
+        let sequence_id = string_header(headers, "sequence_id")?;
+        if sequence_id.is_empty() {
+            return Err(HeaderError::EmptySequenceId);
+        }
+
+        let sequence = integer_header(headers, "sequence")?;
+        let num_sequences = integer_header(headers, "num_sequences")?;
+
+        if num_sequences == 0 {
+            return Err(HeaderError::ZeroNumSequences);
+        }
+        if num_sequences > max_num_sequences {
+            return Err(HeaderError::TooManySequences {
+                num_sequences,
+                max_num_sequences,
+            });
+        }
+        if sequence == 0 {
+            return Err(HeaderError::ZeroSequence);
+        }
+        if sequence > num_sequences {
+            return Err(HeaderError::SequenceOutOfRange {
+                sequence,
+                num_sequences,
+            });
+        }
+
+        Ok(SequenceHeaders {
+            sequence_id,
+            sequence,
+            num_sequences,
+        })
+    }
+}
+
+fn lookup<'a>(headers: &'a FieldTable, header: &'static str) -> Result<&'a AMQPValue, HeaderError> {
+    headers
+        .inner()
+        .get(header)
+        .ok_or(HeaderError::Missing(header))
+}
+
+fn string_header(headers: &FieldTable, header: &'static str) -> Result<String, HeaderError> {
+    match lookup(headers, header)? {
+        AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes())
+            .map(String::from)
+            .map_err(|_| HeaderError::InvalidUtf8(header)),
+        AMQPValue::ShortString(value) => Ok(value.as_str().to_string()),
+        other => Err(HeaderError::WrongType {
+            header,
+            found: other.get_type(),
+        }),
+    }
+}
+
+fn integer_header(headers: &FieldTable, header: &'static str) -> Result<u64, HeaderError> {
+    let signed = match lookup(headers, header)? {
+        AMQPValue::ShortShortUInt(value) => return Ok(u64::from(*value)),
+        AMQPValue::ShortUInt(value) => return Ok(u64::from(*value)),
+        AMQPValue::LongUInt(value) => return Ok(u64::from(*value)),
+        AMQPValue::ShortShortInt(value) => i64::from(*value),
+        AMQPValue::ShortInt(value) => i64::from(*value),
+        AMQPValue::LongInt(value) => i64::from(*value),
+        AMQPValue::LongLongInt(value) => *value,
+        other => {
+            return Err(HeaderError::WrongType {
+                header,
+                found: other.get_type(),
+            })
+        }
+    };
+
+    u64::try_from(signed).map_err(|_| HeaderError::Negative {
+        header,
+        value: signed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::types::{LongString, ShortString};
+
+    const MAX: u64 = 100;
+
+    fn headers(values: &[(&str, AMQPValue)]) -> FieldTable {
+        let mut headers = FieldTable::default();
+        for (name, value) in values {
+            headers.insert(ShortString::from(*name), value.clone());
+        }
+        headers
+    }
+
+    fn sequence(sequence: AMQPValue, num_sequences: AMQPValue) -> FieldTable {
+        headers(&[
+            (
+                "sequence_id",
+                AMQPValue::LongString(LongString::from("abc")),
+            ),
+            ("sequence", sequence),
+            ("num_sequences", num_sequences),
+        ])
+    }
+
+    #[test]
+    fn valid_headers_are_parsed() {
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(
+                AMQPValue::ShortShortUInt(2),
+                AMQPValue::LongLongInt(3),
+            )),
+            MAX,
+        );
+        assert_eq!(
+            parsed,
+            Ok(SequenceHeaders {
+                sequence_id: "abc".to_string(),
+                sequence: 2,
+                num_sequences: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn missing_headers_are_refused() {
+        assert_eq!(
+            SequenceHeaders::parse(None, MAX),
+            Err(HeaderError::Missing("sequence_id"))
+        );
+        let partial = headers(&[
+            (
+                "sequence_id",
+                AMQPValue::LongString(LongString::from("abc")),
+            ),
+            ("sequence", AMQPValue::LongInt(1)),
+        ]);
+        assert_eq!(
+            SequenceHeaders::parse(Some(&partial), MAX),
+            Err(HeaderError::Missing("num_sequences"))
+        );
+    }
+
+    #[test]
+    fn headers_of_the_wrong_type_are_refused() {
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(
+                AMQPValue::LongString(LongString::from("1")),
+                AMQPValue::LongInt(3),
+            )),
+            MAX,
+        );
+        assert_eq!(parsed.unwrap_err().reason(), "wrong_header_type");
+
+        let parsed = SequenceHeaders::parse(
+            Some(&headers(&[("sequence_id", AMQPValue::LongInt(1))])),
+            MAX,
+        );
+        assert_eq!(parsed.unwrap_err().reason(), "wrong_header_type");
+
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(AMQPValue::LongInt(-1), AMQPValue::LongInt(3))),
+            MAX,
+        );
+        assert_eq!(
+            parsed,
+            Err(HeaderError::Negative {
+                header: "sequence",
+                value: -1
+            })
+        );
+    }
+
+    #[test]
+    fn zero_headers_are_refused() {
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(AMQPValue::LongInt(1), AMQPValue::LongInt(0))),
+            MAX,
+        );
+        assert_eq!(parsed, Err(HeaderError::ZeroNumSequences));
+
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(AMQPValue::LongInt(0), AMQPValue::LongInt(3))),
+            MAX,
+        );
+        assert_eq!(parsed, Err(HeaderError::ZeroSequence));
+    }
+
+    #[test]
+    fn sequences_beyond_num_sequences_are_refused() {
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(AMQPValue::LongInt(4), AMQPValue::LongInt(3))),
+            MAX,
+        );
+        assert_eq!(
+            parsed,
+            Err(HeaderError::SequenceOutOfRange {
+                sequence: 4,
+                num_sequences: 3
+            })
+        );
+    }
+
+    #[test]
+    fn num_sequences_over_the_maximum_are_refused() {
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(
+                AMQPValue::LongInt(1),
+                AMQPValue::LongLongInt(i64::MAX),
+            )),
+            MAX,
+        );
+        assert_eq!(
+            parsed,
+            Err(HeaderError::TooManySequences {
+                num_sequences: i64::MAX as u64,
+                max_num_sequences: MAX
+            })
+        );
+        assert_eq!(parsed.unwrap_err().reason(), "num_sequences_too_large");
+
+        let parsed = SequenceHeaders::parse(
+            Some(&sequence(AMQPValue::LongInt(1), AMQPValue::LongInt(100))),
+            MAX,
+        );
+        assert!(parsed.is_ok());
+    }
+}
@@ -0,0 +1,8 @@
+//! The resequencer service, which wires the [`resequencer_core`] to RabbitMQ.
+//!
+//! The binary consumes parts from a queue and publishes the releases, while the benchmark binary drives
+//! [`resequencer_core::resequencer::Resequencer`] on its own.
+
+pub mod config;
+pub mod headers;
+pub mod shards;
@@ -0,0 +1,141 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    ffi::OsString,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+use tokio::sync::Mutex;
+
+use resequencer_core::{
+    resequencer::{EvictionPolicy, Release, Resequencer},
+    state::State,
+    store::{FileStore, MemoryStore, StateStore, StoreEvent},
+};
+
+use crate::config::Config;
+
+/// The state split into shards by the hash of the `sequence_id`, each behind its own lock,
+/// so that unrelated sequences are resequenced in parallel.
+///
+/// Every shard persists to its own store. With a state file of `resequencer-state.jsonl`, shard 0
+/// persists to `resequencer-state.jsonl.0`, shard 1 to `resequencer-state.jsonl.1`, and so on.
+pub struct Shards {
+    shards: Vec<Mutex<State>>,
+}
+
+impl Shards {
+    /// Opens the store of every shard and recovers the state persisted by a previous run, returning the
+    /// releases that were ready, but had not been published before the process stopped, along with the
+    /// index of their shard.
+    ///
+    /// The previous run may have had a different number of shards, so every event is routed to its shard anew.
+    pub fn recover(config: &Config) -> io::Result<(Shards, Vec<(usize, Release)>)> {
+        let count = config.shards;
+        let mut events: Vec<Vec<StoreEvent>> = (0..count).map(|_| Vec::new()).collect();
+        let mut stores: Vec<Box<dyn StateStore>> = Vec::with_capacity(count);
+
+        for index in 0..count {
+            let mut store: Box<dyn StateStore> = match &config.state_file {
+                Some(path) => Box::new(FileStore::open(shard_path(path, index))?),
+                None => Box::new(MemoryStore),
+            };
+            let loaded = store.load()?;
+            if loaded.skipped > 0 {
+                println!(
+                    "[Warning] Ignoring {} truncated event(s) in the state of shard {}",
+                    loaded.skipped, index
+                );
+            }
+            for event in loaded.events {
+                events[shard_of(event.sequence_id(), count)].push(event);
+            }
+            stores.push(store);
+        }
+
+        // Files of shards that no longer exist, and the single file used before the state was sharded
+        let leftovers: Vec<PathBuf> = match &config.state_file {
+            Some(path) => (count..)
+                .map(|index| shard_path(path, index))
+                .take_while(|leftover| leftover.exists())
+                .chain(Some(path.clone()).filter(|path| path.exists()))
+                .collect(),
+            None => Vec::new(),
+        };
+        for leftover in &leftovers {
+            let loaded = FileStore::open(leftover)?.load()?;
+            if loaded.skipped > 0 {
+                println!(
+                    "[Warning] Ignoring {} truncated event(s) in '{}'",
+                    loaded.skipped,
+                    leftover.display()
+                );
+            }
+            for event in loaded.events {
+                events[shard_of(event.sequence_id(), count)].push(event);
+            }
+        }
+
+        // Divide the cap on open sequences among the shards, rounding up
+        let eviction = EvictionPolicy {
+            max_open_sequences: config
+                .eviction
+                .max_open_sequences
+                .map(|max| max.div_ceil(count)),
+            ..config.eviction
+        };
+
+        let mut shards = Vec::with_capacity(count);
+        let mut releases = Vec::new();
+        for (index, (store, events)) in stores.into_iter().zip(events).enumerate() {
+            let resequencer = Resequencer::new(config.mode, eviction, config.duplicates);
+            let recovered_events = events.len();
+            let (state, ready) = State::recover(resequencer, store, events)?;
+            if recovered_events > 0 {
+                println!(
+                    "[Information] Recovered {} stored event(s) of shard {}, {} release(s) are ready",
+                    recovered_events,
+                    index,
+                    ready.len()
+                );
+            }
+            shards.push(Mutex::new(state));
+            releases.extend(ready.into_iter().map(|release| (index, release)));
+        }
+
+        // Their events live on in the compacted stores of the current shards
+        for leftover in &leftovers {
+            fs::remove_file(leftover)?;
+        }
+
+        Ok((Shards { shards }, releases))
+    }
+
+    /// The shard that holds the sequence.
+    pub fn for_sequence(&self, sequence_id: &str) -> &Mutex<State> {
+        &self.shards[shard_of(sequence_id, self.shards.len())]
+    }
+
+    pub fn get(&self, index: usize) -> &Mutex<State> {
+        &self.shards[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Mutex<State>> {
+        self.shards.iter()
+    }
+}
+
+/// Index of the shard out of `count` that a sequence belongs to.
+pub fn shard_of(sequence_id: &str, count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    sequence_id.hash(&mut hasher);
+    (hasher.finish() % count as u64) as usize
+}
+
+fn shard_path(path: &Path, index: usize) -> PathBuf {
+    let mut shard_path = OsString::from(path);
+    shard_path.push(format!(".{}", index));
+    PathBuf::from(shard_path)
+}
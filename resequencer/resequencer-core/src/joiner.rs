@@ -0,0 +1,226 @@
+use std::{fmt, sync::Arc};
+
+/// Why a part cannot be joined by a [`Joiner`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    InvalidJson(String),
+    TooLarge { len: usize },
+}
+
+impl JoinError {
+    /// A short machine readable identifier of the error, attached to dead-lettered messages.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            JoinError::InvalidJson(_) => "invalid_json_part",
+            JoinError::TooLarge { .. } => "part_too_large",
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::InvalidJson(e) => write!(f, "payload is not a JSON document: {}", e),
+            JoinError::TooLarge { len } => {
+                write!(f, "payload of {} bytes exceeds the frame limit", len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// Joins the payloads of a release into the body of the single message that is published downstream.
+///
+/// Every part is checked when it arrives, so that a part the joiner cannot handle is dead-lettered on its own,
+/// rather than failing the release it ends up in.
+pub trait Joiner: fmt::Debug + Send + Sync {
+    /// Checks that `part` can be joined.
+    fn check(&self, _part: &[u8]) -> Result<(), JoinError> {
+        Ok(())
+    }
+
+    /// Joins the parts, in order, each of which has passed [`Joiner::check`].
+    ///
+    /// `first_sequence` is the `sequence` number of the first part. It is greater than 1 for a release that continues
+    /// a sequence, such as in streaming mode, which a joiner that delimits the parts has to delimit from the release
+    /// before it. The releases of a sequence then add up to what it joins to when released at once.
+    fn join(&self, parts: &[Vec<u8>], first_sequence: u64) -> Vec<u8>;
+
+    /// Content type of the joined message, if the joiner determines it.
+    fn content_type(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Concatenates the parts byte for byte, such as the chunks of a file.
+#[derive(Debug, Default)]
+pub struct Concat;
+
+impl Joiner for Concat {
+    fn join(&self, parts: &[Vec<u8>], _first_sequence: u64) -> Vec<u8> {
+        parts.concat()
+    }
+}
+
+/// Puts a newline between the parts, and before the first part of a release that continues a sequence.
+#[derive(Debug, Default)]
+pub struct NewlineDelimited;
+
+impl Joiner for NewlineDelimited {
+    fn join(&self, parts: &[Vec<u8>], first_sequence: u64) -> Vec<u8> {
+        let joined = parts.join(&b'\n');
+        match first_sequence > 1 && !parts.is_empty() {
+            true => [&b"\n"[..], &joined].concat(),
+            false => joined,
+        }
+    }
+}
+
+/// Collects the parts, each of which has to be a JSON document, into a JSON array.
+///
+/// Every release is an array of its own, so a sequence released in several parts is published as several arrays.
+#[derive(Debug, Default)]
+pub struct JsonArray;
+
+impl Joiner for JsonArray {
+    fn check(&self, part: &[u8]) -> Result<(), JoinError> {
+        serde_json::from_slice::<serde::de::IgnoredAny>(part)
+            .map(|_| ())
+            .map_err(|e| JoinError::InvalidJson(e.to_string()))
+    }
+
+    fn join(&self, parts: &[Vec<u8>], _first_sequence: u64) -> Vec<u8> {
+        // The parts are valid JSON already, so they are spliced in as they are rather than parsed again
+        let mut joined = vec![b'['];
+        for (index, part) in parts.iter().enumerate() {
+            if index > 0 {
+                joined.push(b',');
+            }
+            joined.extend_from_slice(part);
+        }
+        joined.push(b']');
+        joined
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        Some("application/json")
+    }
+}
+
+/// Frames every part with its length as a big-endian `u32`, so a consumer can split the message again.
+#[derive(Debug, Default)]
+pub struct LengthPrefixed;
+
+impl Joiner for LengthPrefixed {
+    fn check(&self, part: &[u8]) -> Result<(), JoinError> {
+        match u32::try_from(part.len()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(JoinError::TooLarge { len: part.len() }),
+        }
+    }
+
+    fn join(&self, parts: &[Vec<u8>], _first_sequence: u64) -> Vec<u8> {
+        let mut joined = Vec::with_capacity(parts.iter().map(|part| part.len() + 4).sum());
+        for part in parts {
+            joined.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            joined.extend_from_slice(part);
+        }
+        joined
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        Some("application/octet-stream")
+    }
+}
+
+/// Looks up one of the built-in joiners by the name it is configured with.
+pub fn from_name(name: &str) -> Result<Arc<dyn Joiner>, String> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "concat" => Ok(Arc::new(Concat)),
+        "newline" => Ok(Arc::new(NewlineDelimited)),
+        "json-array" => Ok(Arc::new(JsonArray)),
+        "length-prefixed" => Ok(Arc::new(LengthPrefixed)),
+        other => Err(format!(
+            "Unknown joiner '{}', expected 'concat', 'newline', 'json-array' or 'length-prefixed'",
+            other
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resequencer::{DuplicatePolicy, EvictionPolicy, ReleaseMode, Resequencer, Sequence};
+
+    fn parts(parts: &[&str]) -> Vec<Vec<u8>> {
+        parts.iter().map(|part| part.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn concat_and_newline_join_the_bytes() {
+        let parts = parts(&["ab", "c", ""]);
+        assert_eq!(Concat.join(&parts, 1), b"abc");
+        assert_eq!(NewlineDelimited.join(&parts, 1), b"ab\nc\n");
+        assert_eq!(NewlineDelimited.join(&parts, 4), b"\nab\nc\n");
+    }
+
+    #[test]
+    fn json_array_checks_and_splices_the_parts() {
+        assert!(JsonArray.check(br#"{"a": 1}"#).is_ok());
+        let error = JsonArray.check(b"{not json").unwrap_err();
+        assert_eq!(error.reason(), "invalid_json_part");
+
+        let joined = JsonArray.join(&parts(&[r#"{"a":1}"#, "2", r#""three""#]), 1);
+        let value: serde_json::Value = serde_json::from_slice(&joined).unwrap();
+        assert_eq!(value, serde_json::json!([{"a": 1}, 2, "three"]));
+        assert_eq!(JsonArray.join(&[], 1), b"[]");
+    }
+
+    #[test]
+    fn length_prefixed_frames_every_part() {
+        let joined = LengthPrefixed.join(&parts(&["ab", ""]), 1);
+        assert_eq!(joined, [0, 0, 0, 2, b'a', b'b', 0, 0, 0, 0]);
+    }
+
+    /// Joins the releases of a sequence whose parts arrive in the given order, one after the other.
+    fn released(joiner: &dyn Joiner, mode: ReleaseMode, arrivals: &[u64]) -> Vec<u8> {
+        let eviction = EvictionPolicy {
+            sequence_timeout: None,
+            max_open_sequences: None,
+        };
+        let mut resequencer = Resequencer::new(mode, eviction, DuplicatePolicy::KeepFirst);
+        let mut joined = Vec::new();
+        for &sequence in arrivals {
+            let payload = format!("part {}", sequence).into_bytes();
+            let part = Sequence::new(payload, 4, sequence, "a".to_string(), None);
+            if let Some(release) = resequencer.push_sequence(part).unwrap().release {
+                joined.extend(joiner.join(&release.payloads, release.first_sequence));
+            }
+        }
+        joined
+    }
+
+    #[test]
+    fn streaming_releases_add_up_to_the_batch_release() {
+        let arrivals = [1, 3, 2, 4];
+        for name in ["concat", "newline", "length-prefixed"] {
+            let joiner = from_name(name).unwrap();
+            let batch = released(joiner.as_ref(), ReleaseMode::Batch, &arrivals);
+            let streaming = released(joiner.as_ref(), ReleaseMode::Streaming, &arrivals);
+            assert_eq!(streaming, batch, "{}", name);
+        }
+        assert_eq!(
+            released(&NewlineDelimited, ReleaseMode::Streaming, &arrivals),
+            b"part 1\npart 2\npart 3\npart 4"
+        );
+    }
+
+    #[test]
+    fn from_name_knows_the_built_in_joiners() {
+        for name in ["concat", "newline", "JSON-Array", "length-prefixed"] {
+            assert!(from_name(name).is_ok(), "{}", name);
+        }
+        assert!(from_name("zip").is_err());
+    }
+}
@@ -0,0 +1,11 @@
+//! The reassembly core of the resequencer, independent of any broker.
+//!
+//! [`resequencer::Resequencer`] collects the parts of a sequence arriving in any order and releases them in
+//! order, [`state::State`] persists it to a [`store::StateStore`] so that it survives a restart, and
+//! [`joiner::Joiner`] turns a release into a single payload. Delivery tags are opaque to the core, so a
+//! caller can use any token that settles the parts with its broker.
+
+pub mod joiner;
+pub mod resequencer;
+pub mod state;
+pub mod store;
@@ -0,0 +1,1072 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::store::StoreEvent;
+
+#[derive(Debug)]
+pub struct Sequence {
+    pub payload: Vec<u8>,
+    pub num_sequences: u64,
+    pub sequence: u64,
+    pub sequence_id: String,
+    /// Delivery tag of the message carrying this part, acknowledged once the part has been handed downstream.
+    /// `None` when the message has been acknowledged already. The core never interprets it, so any token that
+    /// identifies the message to the caller will do.
+    pub delivery_tag: Option<u64>,
+}
+
+impl Sequence {
+    /// Creates a new [`Sequence`].
+    pub fn new(
+        payload: Vec<u8>,
+        num_sequences: u64,
+        sequence: u64,
+        sequence_id: String,
+        delivery_tag: Option<u64>,
+    ) -> Self {
+        Self {
+            payload,
+            num_sequences,
+            sequence,
+            sequence_id,
+            delivery_tag,
+        }
+    }
+}
+
+/// Controls when the [`Resequencer`] hands payloads downstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReleaseMode {
+    /// Wait until every part of a sequence has been received, then release them all at once.
+    Batch,
+    /// Release each contiguous run of parts as soon as the gap before it has been closed.
+    Streaming,
+}
+
+impl FromStr for ReleaseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "batch" => Ok(ReleaseMode::Batch),
+            "streaming" => Ok(ReleaseMode::Streaming),
+            other => Err(format!(
+                "Unknown release mode '{}', expected 'batch' or 'streaming'",
+                other
+            )),
+        }
+    }
+}
+
+/// What to do with a part whose `sequence` has already been received for its `sequence_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep the part that arrived first and discard the duplicate.
+    KeepFirst,
+    /// Replace the held part with the duplicate. Parts that have already been released cannot be replaced,
+    /// so duplicates of those are discarded.
+    KeepLast,
+    /// Reject the duplicate, so it can be dead-lettered.
+    Reject,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "keep-first" => Ok(DuplicatePolicy::KeepFirst),
+            "keep-last" => Ok(DuplicatePolicy::KeepLast),
+            "reject" => Ok(DuplicatePolicy::Reject),
+            other => Err(format!(
+                "Unknown duplicate policy '{}', expected 'keep-first', 'keep-last' or 'reject'",
+                other
+            )),
+        }
+    }
+}
+
+/// Limits on how long and how many incomplete sequences the [`Resequencer`] holds on to.
+#[derive(Debug, Clone, Copy)]
+pub struct EvictionPolicy {
+    /// How long a sequence may stay open after its first part arrived. `None` never times out.
+    pub sequence_timeout: Option<Duration>,
+    /// How many sequences may be open at once. `None` is unbounded.
+    pub max_open_sequences: Option<usize>,
+}
+
+/// A received part that has not been released yet.
+#[derive(Debug, Clone)]
+struct Part {
+    payload: Vec<u8>,
+    delivery_tag: Option<u64>,
+}
+
+/// The parts collected so far for a single `sequence_id`.
+#[derive(Debug)]
+struct PendingSequence {
+    parts: Vec<Option<Part>>,
+    /// Zero based index of the next part that has yet to be released downstream.
+    next_expected: usize,
+    opened_at: Instant,
+}
+
+impl PendingSequence {
+    fn new(num_sequences: usize) -> Self {
+        Self {
+            parts: vec![None; num_sequences],
+            next_expected: 0,
+            opened_at: Instant::now(),
+        }
+    }
+
+    /// Whether every part that has not been released yet has been received.
+    fn is_complete(&self) -> bool {
+        self.parts[self.next_expected..].iter().all(|s| s.is_some())
+    }
+
+    /// Takes the contiguous run of received parts starting at the cursor, advancing the cursor past them.
+    fn take_contiguous(&mut self) -> Vec<Part> {
+        let mut run = Vec::new();
+        while let Some(slot) = self.parts.get_mut(self.next_expected) {
+            match slot.take() {
+                Some(part) => run.push(part),
+                None => break,
+            }
+            self.next_expected += 1;
+        }
+        run
+    }
+
+    /// The `sequence` numbers that have been received, including those already released.
+    fn received(&self) -> Vec<u64> {
+        (0..self.parts.len())
+            .filter(|&i| i < self.next_expected || self.parts[i].is_some())
+            .map(|i| i as u64 + 1)
+            .collect()
+    }
+
+    /// Delivery tags of the parts that are held, but have not been released.
+    fn held_delivery_tags(&self) -> Vec<u64> {
+        self.parts
+            .iter()
+            .flatten()
+            .filter_map(|part| part.delivery_tag)
+            .collect()
+    }
+
+    /// The `sequence` numbers that have not been received yet.
+    fn missing(&self) -> Vec<u64> {
+        (self.next_expected..self.parts.len())
+            .filter(|&i| self.parts[i].is_none())
+            .map(|i| i as u64 + 1)
+            .collect()
+    }
+}
+
+/// Payloads handed downstream for a `sequence_id`, in order.
+#[derive(Debug)]
+pub struct Release {
+    pub sequence_id: String,
+    /// The `sequence` number of the first payload in `payloads`.
+    pub first_sequence: u64,
+    pub payloads: Vec<Vec<u8>>,
+    /// Delivery tags of the messages the payloads arrived in, which have not been acknowledged yet.
+    pub delivery_tags: Vec<u64>,
+    /// Whether this release finishes the sequence.
+    pub complete: bool,
+}
+
+impl Release {
+    /// The `sequence` number of the last payload in `payloads`.
+    pub fn last_sequence(&self) -> u64 {
+        self.first_sequence + self.payloads.len() as u64 - 1
+    }
+}
+
+/// The outcome of handing a part to [`Resequencer::push_sequence`].
+#[derive(Debug, Default)]
+pub struct Pushed {
+    /// Whatever is ready to be released.
+    pub release: Option<Release>,
+    /// Delivery tag of a duplicate part that was discarded, to be acknowledged without being released.
+    pub discarded: Option<u64>,
+}
+
+/// Whether a part is to be stored, as decided by [`Resequencer::admit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Store,
+    Discard,
+}
+
+/// Why [`Resequencer::push_sequence`] rejected a part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushError {
+    /// The `sequence` has already been received, and the [`DuplicatePolicy`] is to reject duplicates.
+    Duplicate { sequence: u64 },
+    /// The `num_sequences` differs from that of the parts received before for the same `sequence_id`.
+    NumSequencesConflict { num_sequences: u64, expected: u64 },
+    /// The `sequence` is not between 1 and `num_sequences`.
+    SequenceOutOfRange { sequence: u64, num_sequences: u64 },
+}
+
+impl PushError {
+    /// A short machine readable identifier of the error, attached to dead-lettered messages.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PushError::Duplicate { .. } => "duplicate_sequence",
+            PushError::NumSequencesConflict { .. } => "num_sequences_conflict",
+            PushError::SequenceOutOfRange { .. } => "sequence_out_of_range",
+        }
+    }
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PushError::Duplicate { sequence } => {
+                write!(f, "sequence {} has already been received", sequence)
+            }
+            PushError::NumSequencesConflict {
+                num_sequences,
+                expected,
+            } => write!(
+                f,
+                "num_sequences is {}, but earlier parts of the sequence_id had {}",
+                num_sequences, expected
+            ),
+            PushError::SequenceOutOfRange {
+                sequence,
+                num_sequences,
+            } => write!(
+                f,
+                "sequence {} is not between 1 and num_sequences {}",
+                sequence, num_sequences
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PushError {}
+
+/// Why an incomplete sequence was evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// The sequence outlived [`EvictionPolicy::sequence_timeout`].
+    Timeout,
+    /// Too many sequences were open, and this was the oldest.
+    Capacity,
+    /// The sequence was discarded on request, such as by an operator.
+    Discarded,
+}
+
+/// Report of a sequence that was evicted before all of its parts arrived.
+#[derive(Debug, Serialize)]
+pub struct IncompleteSequence {
+    pub sequence_id: String,
+    pub num_sequences: u64,
+    pub reason: EvictionReason,
+    /// Milliseconds between the first part arriving and the eviction.
+    pub open_for_ms: u128,
+    pub received: Vec<u64>,
+    pub missing: Vec<u64>,
+    /// Delivery tags of the parts that were held when the sequence was evicted.
+    #[serde(skip)]
+    pub delivery_tags: Vec<u64>,
+}
+
+impl IncompleteSequence {
+    fn new(
+        sequence_id: String,
+        pending: &PendingSequence,
+        reason: EvictionReason,
+        now: Instant,
+    ) -> Self {
+        Self {
+            sequence_id,
+            num_sequences: pending.parts.len() as u64,
+            reason,
+            open_for_ms: now.saturating_duration_since(pending.opened_at).as_millis(),
+            received: pending.received(),
+            missing: pending.missing(),
+            delivery_tags: pending.held_delivery_tags(),
+        }
+    }
+}
+
+/// Summary of a sequence that is still open, for inspection.
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenSequence {
+    pub sequence_id: String,
+    pub num_sequences: u64,
+    /// Every part up to and including this `sequence` number has been released.
+    pub released_through: u64,
+    /// Milliseconds since the first part arrived.
+    pub open_for_ms: u128,
+    pub received: Vec<u64>,
+    pub missing: Vec<u64>,
+}
+
+impl OpenSequence {
+    fn new(sequence_id: &str, pending: &PendingSequence, now: Instant) -> Self {
+        Self {
+            sequence_id: sequence_id.to_string(),
+            num_sequences: pending.parts.len() as u64,
+            released_through: pending.next_expected as u64,
+            open_for_ms: now.saturating_duration_since(pending.opened_at).as_millis(),
+            received: pending.received(),
+            missing: pending.missing(),
+        }
+    }
+}
+
+/// A part that is held by an open sequence.
+#[derive(Debug, Clone, Serialize)]
+pub struct HeldPart {
+    pub sequence: u64,
+    #[serde(with = "crate::store::base64_payload")]
+    pub payload: Vec<u8>,
+    pub delivery_tag: Option<u64>,
+}
+
+/// An open sequence along with the parts it holds.
+#[derive(Debug, Clone, Serialize)]
+pub struct SequenceDump {
+    #[serde(flatten)]
+    pub sequence: OpenSequence,
+    pub held: Vec<HeldPart>,
+}
+
+#[derive(Debug)]
+pub struct Resequencer {
+    mode: ReleaseMode,
+    eviction: EvictionPolicy,
+    duplicates: DuplicatePolicy,
+    sequences: HashMap<String, PendingSequence>,
+}
+
+impl Resequencer {
+    pub fn new(
+        mode: ReleaseMode,
+        eviction: EvictionPolicy,
+        duplicates: DuplicatePolicy,
+    ) -> Resequencer {
+        Resequencer {
+            mode,
+            eviction,
+            duplicates,
+            sequences: HashMap::new(),
+        }
+    }
+
+    /// Decides whether `new_sequence` would be stored or discarded by [`Resequencer::push_sequence`],
+    /// without changing anything.
+    pub fn admit(&self, new_sequence: &Sequence) -> Result<Admission, PushError> {
+        if new_sequence.sequence == 0 || new_sequence.sequence > new_sequence.num_sequences {
+            return Err(PushError::SequenceOutOfRange {
+                sequence: new_sequence.sequence,
+                num_sequences: new_sequence.num_sequences,
+            });
+        }
+
+        let pending = match self.sequences.get(&new_sequence.sequence_id) {
+            Some(pending) => pending,
+            None => return Ok(Admission::Store),
+        };
+
+        let expected = pending.parts.len() as u64;
+        if new_sequence.num_sequences != expected {
+            return Err(PushError::NumSequencesConflict {
+                num_sequences: new_sequence.num_sequences,
+                expected,
+            });
+        }
+
+        let index = new_sequence.sequence as usize - 1;
+        let released = index < pending.next_expected;
+        if !released && pending.parts[index].is_none() {
+            return Ok(Admission::Store);
+        }
+
+        match self.duplicates {
+            DuplicatePolicy::Reject => Err(PushError::Duplicate {
+                sequence: new_sequence.sequence,
+            }),
+            DuplicatePolicy::KeepLast if !released => Ok(Admission::Store),
+            DuplicatePolicy::KeepFirst | DuplicatePolicy::KeepLast => Ok(Admission::Discard),
+        }
+    }
+
+    /// Stores the payload of `new_sequence` and returns whatever is ready to be released according to the [`ReleaseMode`].
+    ///
+    /// Duplicates are handled according to the [`DuplicatePolicy`], while a `num_sequences` that differs from
+    /// that of the earlier parts of the sequence, or a `sequence` outside of it, is always rejected.
+    pub fn push_sequence(&mut self, new_sequence: Sequence) -> Result<Pushed, PushError> {
+        if self.admit(&new_sequence)? == Admission::Discard {
+            return Ok(Pushed {
+                release: None,
+                discarded: new_sequence.delivery_tag,
+            });
+        }
+
+        // If sequence_id exists, update or insert payload at the appropriate sequence number
+        let pending = self
+            .sequences
+            .entry(new_sequence.sequence_id.clone())
+            .or_insert_with(|| PendingSequence::new(new_sequence.num_sequences as usize));
+
+        let replaced = pending.parts[new_sequence.sequence as usize - 1].replace(Part {
+            payload: new_sequence.payload,
+            delivery_tag: new_sequence.delivery_tag,
+        });
+
+        Ok(Pushed {
+            release: self.take_release(new_sequence.sequence_id),
+            discarded: replaced.and_then(|part| part.delivery_tag),
+        })
+    }
+
+    /// Takes whatever of the sequence is ready to be released according to the [`ReleaseMode`].
+    fn take_release(&mut self, sequence_id: String) -> Option<Release> {
+        let pending = self.sequences.get_mut(&sequence_id)?;
+
+        if self.mode == ReleaseMode::Batch && !pending.is_complete() {
+            return None;
+        }
+
+        let first_sequence = pending.next_expected as u64 + 1;
+        let run = pending.take_contiguous();
+        if run.is_empty() {
+            return None;
+        }
+        let delivery_tags = run.iter().filter_map(|part| part.delivery_tag).collect();
+        let payloads = run.into_iter().map(|part| part.payload).collect();
+        let complete = pending.next_expected == pending.parts.len();
+
+        // Clear the sequences for this sequence_id once everything has been released
+        if complete {
+            self.sequences.remove(&sequence_id);
+        }
+
+        Some(Release {
+            sequence_id,
+            first_sequence,
+            payloads,
+            delivery_tags,
+            complete,
+        })
+    }
+
+    /// Rebuilds the open sequences from the events recorded by a [`StateStore`](crate::store::StateStore).
+    ///
+    /// Recovered parts have no delivery tag, since their messages were acknowledged once the parts were stored.
+    /// The timeout of a recovered sequence starts over.
+    pub fn restore(&mut self, events: Vec<StoreEvent>) {
+        for event in events {
+            match event {
+                StoreEvent::Opened {
+                    sequence_id,
+                    num_sequences,
+                    released_through,
+                } => {
+                    let mut pending = PendingSequence::new(num_sequences as usize);
+                    pending.next_expected = (released_through as usize).min(pending.parts.len());
+                    self.sequences.insert(sequence_id, pending);
+                }
+                StoreEvent::Part {
+                    sequence_id,
+                    num_sequences,
+                    sequence,
+                    payload,
+                } => {
+                    let pending = self
+                        .sequences
+                        .entry(sequence_id)
+                        .or_insert_with(|| PendingSequence::new(num_sequences as usize));
+                    let index = (sequence as usize).wrapping_sub(1);
+                    if index >= pending.next_expected && index < pending.parts.len() {
+                        pending.parts[index] = Some(Part {
+                            payload,
+                            delivery_tag: None,
+                        });
+                    }
+                }
+                // Released and removed events may be recorded after the sequence was done with already
+                StoreEvent::Released {
+                    sequence_id,
+                    through,
+                } => {
+                    if let Some(pending) = self.sequences.get_mut(&sequence_id) {
+                        let through = (through as usize).min(pending.parts.len());
+                        for slot in pending.parts[..through].iter_mut() {
+                            *slot = None;
+                        }
+                        pending.next_expected = pending.next_expected.max(through);
+                    }
+                }
+                StoreEvent::Removed { sequence_id } => {
+                    self.sequences.remove(&sequence_id);
+                }
+            }
+        }
+    }
+
+    /// Takes the releases of every sequence that is ready to be released, such as those that were
+    /// ready, but had not been published before the process stopped.
+    pub fn take_ready(&mut self) -> Vec<Release> {
+        let sequence_ids: Vec<String> = self.sequences.keys().cloned().collect();
+        sequence_ids
+            .into_iter()
+            .filter_map(|sequence_id| self.take_release(sequence_id))
+            .collect()
+    }
+
+    /// Describes the open sequences as events, from which [`Resequencer::restore`] rebuilds the same state.
+    pub fn snapshot(&self) -> Vec<StoreEvent> {
+        let mut events = Vec::new();
+        for (sequence_id, pending) in &self.sequences {
+            let num_sequences = pending.parts.len() as u64;
+            events.push(StoreEvent::Opened {
+                sequence_id: sequence_id.clone(),
+                num_sequences,
+                released_through: pending.next_expected as u64,
+            });
+            for (index, part) in pending.parts.iter().enumerate() {
+                if let Some(part) = part {
+                    events.push(StoreEvent::Part {
+                        sequence_id: sequence_id.clone(),
+                        num_sequences,
+                        sequence: index as u64 + 1,
+                        payload: part.payload.clone(),
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    /// Describes every open sequence, oldest first.
+    pub fn open_sequences(&self, now: Instant) -> Vec<OpenSequence> {
+        let mut by_age: Vec<(&String, &PendingSequence)> = self.sequences.iter().collect();
+        by_age.sort_by_key(|(_, pending)| pending.opened_at);
+        by_age
+            .into_iter()
+            .map(|(sequence_id, pending)| OpenSequence::new(sequence_id, pending, now))
+            .collect()
+    }
+
+    /// Describes an open sequence along with the parts it holds, or `None` if it is not open.
+    pub fn dump(&self, sequence_id: &str, now: Instant) -> Option<SequenceDump> {
+        let pending = self.sequences.get(sequence_id)?;
+        let held = pending
+            .parts
+            .iter()
+            .enumerate()
+            .filter_map(|(index, part)| {
+                part.as_ref().map(|part| HeldPart {
+                    sequence: index as u64 + 1,
+                    payload: part.payload.clone(),
+                    delivery_tag: part.delivery_tag,
+                })
+            })
+            .collect();
+        Some(SequenceDump {
+            sequence: OpenSequence::new(sequence_id, pending, now),
+            held,
+        })
+    }
+
+    /// Closes an open sequence without waiting for its missing parts, releasing the parts it holds despite
+    /// the gaps between them. Every contiguous run of parts is a release of its own, and the last one
+    /// is marked complete, as nothing follows it.
+    ///
+    /// Returns `None` if the sequence is not open, and no releases if it holds no parts.
+    pub fn flush(&mut self, sequence_id: &str) -> Option<Vec<Release>> {
+        let mut pending = self.sequences.remove(sequence_id)?;
+        let mut releases: Vec<Release> = Vec::new();
+
+        while pending.next_expected < pending.parts.len() {
+            if pending.parts[pending.next_expected].is_none() {
+                pending.next_expected += 1;
+                continue;
+            }
+            let first_sequence = pending.next_expected as u64 + 1;
+            let run = pending.take_contiguous();
+            releases.push(Release {
+                sequence_id: sequence_id.to_string(),
+                first_sequence,
+                delivery_tags: run.iter().filter_map(|part| part.delivery_tag).collect(),
+                payloads: run.into_iter().map(|part| part.payload).collect(),
+                complete: false,
+            });
+        }
+
+        if let Some(last) = releases.last_mut() {
+            last.complete = true;
+        }
+        Some(releases)
+    }
+
+    /// Removes an open sequence without releasing anything, or returns `None` if it is not open.
+    pub fn discard(&mut self, sequence_id: &str, now: Instant) -> Option<IncompleteSequence> {
+        let pending = self.sequences.remove(sequence_id)?;
+        Some(IncompleteSequence::new(
+            sequence_id.to_string(),
+            &pending,
+            EvictionReason::Discarded,
+            now,
+        ))
+    }
+
+    /// Removes every sequence that has outlived the timeout at `now`, followed by the oldest sequences
+    /// until no more than the maximum number of sequences are open.
+    pub fn evict(&mut self, now: Instant) -> Vec<IncompleteSequence> {
+        let mut evicted = Vec::new();
+
+        if let Some(timeout) = self.eviction.sequence_timeout {
+            let expired: Vec<String> = self
+                .sequences
+                .iter()
+                .filter(|(_, pending)| now.saturating_duration_since(pending.opened_at) >= timeout)
+                .map(|(sequence_id, _)| sequence_id.clone())
+                .collect();
+            for sequence_id in expired {
+                let pending = self.sequences.remove(&sequence_id).unwrap();
+                evicted.push(IncompleteSequence::new(
+                    sequence_id,
+                    &pending,
+                    EvictionReason::Timeout,
+                    now,
+                ));
+            }
+        }
+
+        if let Some(max_open_sequences) = self.eviction.max_open_sequences {
+            let overflow = self.sequences.len().saturating_sub(max_open_sequences);
+            if overflow > 0 {
+                let mut by_age: Vec<(&String, Instant)> = self
+                    .sequences
+                    .iter()
+                    .map(|(sequence_id, pending)| (sequence_id, pending.opened_at))
+                    .collect();
+                by_age.sort_by_key(|&(_, opened_at)| opened_at);
+                let oldest: Vec<String> = by_age
+                    .into_iter()
+                    .take(overflow)
+                    .map(|(sequence_id, _)| sequence_id.clone())
+                    .collect();
+                for sequence_id in oldest {
+                    let pending = self.sequences.remove(&sequence_id).unwrap();
+                    evicted.push(IncompleteSequence::new(
+                        sequence_id,
+                        &pending,
+                        EvictionReason::Capacity,
+                        now,
+                    ));
+                }
+            }
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_EVICTION: EvictionPolicy = EvictionPolicy {
+        sequence_timeout: None,
+        max_open_sequences: None,
+    };
+
+    fn part(sequence_id: &str, num_sequences: u64, sequence: u64) -> Sequence {
+        Sequence::new(
+            format!("{}-{}", sequence_id, sequence).into_bytes(),
+            num_sequences,
+            sequence,
+            sequence_id.to_string(),
+            Some(sequence),
+        )
+    }
+
+    fn payloads(release: &Release) -> Vec<String> {
+        release
+            .payloads
+            .iter()
+            .map(|payload| String::from_utf8(payload.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn batch_releases_out_of_order_parts_in_order_once_complete() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+
+        for sequence in [3, 1] {
+            let pushed = resequencer.push_sequence(part("a", 3, sequence)).unwrap();
+            assert!(pushed.release.is_none());
+        }
+        let release = resequencer
+            .push_sequence(part("a", 3, 2))
+            .unwrap()
+            .release
+            .unwrap();
+
+        assert_eq!(payloads(&release), ["a-1", "a-2", "a-3"]);
+        assert_eq!(release.first_sequence, 1);
+        assert_eq!(release.last_sequence(), 3);
+        assert_eq!(release.delivery_tags, [1, 2, 3]);
+        assert!(release.complete);
+        assert!(resequencer.snapshot().is_empty());
+    }
+
+    #[test]
+    fn streaming_releases_each_contiguous_run() {
+        let mut resequencer = Resequencer::new(
+            ReleaseMode::Streaming,
+            NO_EVICTION,
+            DuplicatePolicy::KeepFirst,
+        );
+
+        assert!(resequencer
+            .push_sequence(part("a", 4, 2))
+            .unwrap()
+            .release
+            .is_none());
+
+        let first = resequencer
+            .push_sequence(part("a", 4, 1))
+            .unwrap()
+            .release
+            .unwrap();
+        assert_eq!(payloads(&first), ["a-1", "a-2"]);
+        assert!(!first.complete);
+
+        let second = resequencer
+            .push_sequence(part("a", 4, 3))
+            .unwrap()
+            .release
+            .unwrap();
+        assert_eq!(second.first_sequence, 3);
+        assert_eq!(payloads(&second), ["a-3"]);
+
+        let last = resequencer
+            .push_sequence(part("a", 4, 4))
+            .unwrap()
+            .release
+            .unwrap();
+        assert_eq!(last.first_sequence, 4);
+        assert!(last.complete);
+    }
+
+    #[test]
+    fn interleaved_sequences_are_kept_apart() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+
+        resequencer.push_sequence(part("a", 2, 2)).unwrap();
+        resequencer.push_sequence(part("b", 2, 1)).unwrap();
+        let a = resequencer.push_sequence(part("a", 2, 1)).unwrap().release;
+        let b = resequencer.push_sequence(part("b", 2, 2)).unwrap().release;
+
+        assert_eq!(payloads(&a.unwrap()), ["a-1", "a-2"]);
+        assert_eq!(payloads(&b.unwrap()), ["b-1", "b-2"]);
+    }
+
+    #[test]
+    fn keep_first_discards_the_duplicate() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        resequencer.push_sequence(part("a", 2, 1)).unwrap();
+
+        let mut duplicate = part("a", 2, 1);
+        duplicate.payload = b"other".to_vec();
+        duplicate.delivery_tag = Some(10);
+        let pushed = resequencer.push_sequence(duplicate).unwrap();
+        assert_eq!(pushed.discarded, Some(10));
+
+        let release = resequencer
+            .push_sequence(part("a", 2, 2))
+            .unwrap()
+            .release
+            .unwrap();
+        assert_eq!(payloads(&release), ["a-1", "a-2"]);
+    }
+
+    #[test]
+    fn keep_last_replaces_held_parts_but_not_released_ones() {
+        let mut resequencer = Resequencer::new(
+            ReleaseMode::Streaming,
+            NO_EVICTION,
+            DuplicatePolicy::KeepLast,
+        );
+        resequencer.push_sequence(part("a", 3, 1)).unwrap();
+        resequencer.push_sequence(part("a", 3, 3)).unwrap();
+
+        // Part 1 has been released already, so its duplicate is discarded
+        let mut released = part("a", 3, 1);
+        released.delivery_tag = Some(11);
+        assert_eq!(
+            resequencer.push_sequence(released).unwrap().discarded,
+            Some(11)
+        );
+
+        // Part 3 is held, so its duplicate replaces it, and the replaced part is discarded
+        let mut held = part("a", 3, 3);
+        held.payload = b"replacement".to_vec();
+        held.delivery_tag = Some(13);
+        assert_eq!(resequencer.push_sequence(held).unwrap().discarded, Some(3));
+
+        let release = resequencer
+            .push_sequence(part("a", 3, 2))
+            .unwrap()
+            .release
+            .unwrap();
+        assert_eq!(payloads(&release), ["a-2", "replacement"]);
+        assert_eq!(release.delivery_tags, [2, 13]);
+    }
+
+    #[test]
+    fn reject_refuses_the_duplicate() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::Reject);
+        resequencer.push_sequence(part("a", 2, 1)).unwrap();
+
+        let error = resequencer.push_sequence(part("a", 2, 1)).unwrap_err();
+        assert_eq!(error, PushError::Duplicate { sequence: 1 });
+        assert_eq!(error.reason(), "duplicate_sequence");
+    }
+
+    #[test]
+    fn conflicting_num_sequences_is_rejected() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        resequencer.push_sequence(part("a", 3, 1)).unwrap();
+
+        let error = resequencer.push_sequence(part("a", 4, 2)).unwrap_err();
+        assert_eq!(
+            error,
+            PushError::NumSequencesConflict {
+                num_sequences: 4,
+                expected: 3
+            }
+        );
+    }
+
+    #[test]
+    fn sequence_outside_num_sequences_is_rejected() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+
+        for (num_sequences, sequence) in [(3, 0), (3, 4), (0, 1)] {
+            let error = resequencer
+                .push_sequence(part("a", num_sequences, sequence))
+                .unwrap_err();
+            assert_eq!(
+                error,
+                PushError::SequenceOutOfRange {
+                    sequence,
+                    num_sequences
+                }
+            );
+        }
+        assert!(resequencer.snapshot().is_empty());
+    }
+
+    #[test]
+    fn timeout_evicts_and_reports_the_gaps() {
+        let eviction = EvictionPolicy {
+            sequence_timeout: Some(Duration::from_secs(5)),
+            max_open_sequences: None,
+        };
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Streaming, eviction, DuplicatePolicy::KeepFirst);
+        resequencer.push_sequence(part("a", 5, 1)).unwrap();
+        resequencer.push_sequence(part("a", 5, 4)).unwrap();
+
+        assert!(resequencer.evict(Instant::now()).is_empty());
+
+        let evicted = resequencer.evict(Instant::now() + Duration::from_secs(6));
+        assert_eq!(evicted.len(), 1);
+        let incomplete = &evicted[0];
+        assert_eq!(incomplete.sequence_id, "a");
+        assert_eq!(incomplete.reason, EvictionReason::Timeout);
+        assert_eq!(incomplete.received, [1, 4]);
+        assert_eq!(incomplete.missing, [2, 3, 5]);
+        // Part 1 was released, so only the held part 4 is left to settle
+        assert_eq!(incomplete.delivery_tags, [4]);
+        assert!(resequencer.snapshot().is_empty());
+    }
+
+    #[test]
+    fn capacity_evicts_the_oldest_sequences() {
+        let eviction = EvictionPolicy {
+            sequence_timeout: None,
+            max_open_sequences: Some(2),
+        };
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, eviction, DuplicatePolicy::KeepFirst);
+        for sequence_id in ["a", "b", "c"] {
+            resequencer.push_sequence(part(sequence_id, 2, 1)).unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        let evicted = resequencer.evict(Instant::now());
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].sequence_id, "a");
+        assert_eq!(evicted[0].reason, EvictionReason::Capacity);
+        assert_eq!(evicted[0].missing, [2]);
+    }
+
+    #[test]
+    fn snapshot_restores_the_same_state() {
+        let mut resequencer = Resequencer::new(
+            ReleaseMode::Streaming,
+            NO_EVICTION,
+            DuplicatePolicy::KeepFirst,
+        );
+        resequencer.push_sequence(part("a", 4, 1)).unwrap();
+        resequencer.push_sequence(part("a", 4, 3)).unwrap();
+        resequencer.push_sequence(part("b", 2, 2)).unwrap();
+
+        let mut restored = Resequencer::new(
+            ReleaseMode::Streaming,
+            NO_EVICTION,
+            DuplicatePolicy::KeepFirst,
+        );
+        restored.restore(resequencer.snapshot());
+        assert!(restored.take_ready().is_empty());
+
+        // Part 1 was released before the snapshot, so part 2 releases through 3
+        let mut recovered = part("a", 4, 2);
+        recovered.delivery_tag = None;
+        let release = restored.push_sequence(recovered).unwrap().release.unwrap();
+        assert_eq!(release.first_sequence, 2);
+        assert_eq!(payloads(&release), ["a-2", "a-3"]);
+        // Recovered parts carry no delivery tag
+        assert!(release.delivery_tags.is_empty());
+    }
+
+    #[test]
+    fn restore_replays_released_and_removed_events() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        let stored = |sequence_id: &str, sequence| StoreEvent::Part {
+            sequence_id: sequence_id.to_string(),
+            num_sequences: 2,
+            sequence,
+            payload: vec![sequence as u8],
+        };
+        resequencer.restore(vec![
+            stored("a", 1),
+            stored("a", 2),
+            stored("b", 1),
+            stored("c", 1),
+            stored("c", 2),
+            StoreEvent::Released {
+                sequence_id: "b".to_string(),
+                through: 1,
+            },
+            StoreEvent::Removed {
+                sequence_id: "c".to_string(),
+            },
+        ]);
+
+        // Only "a" was complete and not yet published
+        let ready = resequencer.take_ready();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].sequence_id, "a");
+        assert_eq!(ready[0].payloads, [vec![1], vec![2]]);
+
+        let release = resequencer
+            .push_sequence(Sequence::new(vec![2], 2, 2, "b".to_string(), None))
+            .unwrap()
+            .release
+            .unwrap();
+        assert_eq!(release.first_sequence, 2);
+        assert!(release.complete);
+    }
+
+    #[test]
+    fn policies_parse_from_their_names() {
+        assert_eq!(
+            "Streaming".parse::<ReleaseMode>(),
+            Ok(ReleaseMode::Streaming)
+        );
+        assert_eq!(
+            " keep-last ".parse::<DuplicatePolicy>(),
+            Ok(DuplicatePolicy::KeepLast)
+        );
+        assert!("first".parse::<DuplicatePolicy>().is_err());
+    }
+
+    #[test]
+    fn open_sequences_are_listed_oldest_first() {
+        let mut resequencer = Resequencer::new(
+            ReleaseMode::Streaming,
+            NO_EVICTION,
+            DuplicatePolicy::KeepFirst,
+        );
+        resequencer.push_sequence(part("a", 4, 1)).unwrap();
+        resequencer.push_sequence(part("a", 4, 3)).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        resequencer.push_sequence(part("b", 2, 2)).unwrap();
+
+        let open = resequencer.open_sequences(Instant::now());
+        assert_eq!(open.len(), 2);
+        assert_eq!(open[0].sequence_id, "a");
+        assert_eq!(open[0].released_through, 1);
+        assert_eq!(open[0].received, [1, 3]);
+        assert_eq!(open[0].missing, [2, 4]);
+        assert_eq!(open[1].sequence_id, "b");
+
+        let dump = resequencer.dump("a", Instant::now()).unwrap();
+        assert_eq!(dump.held.len(), 1);
+        assert_eq!(dump.held[0].sequence, 3);
+        assert_eq!(dump.held[0].payload, b"a-3");
+        assert!(resequencer.dump("c", Instant::now()).is_none());
+    }
+
+    #[test]
+    fn flush_releases_every_run_despite_the_gaps() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        for sequence in [2, 3, 5] {
+            resequencer.push_sequence(part("a", 6, sequence)).unwrap();
+        }
+
+        let releases = resequencer.flush("a").unwrap();
+        assert_eq!(releases.len(), 2);
+        assert_eq!(releases[0].first_sequence, 2);
+        assert_eq!(payloads(&releases[0]), ["a-2", "a-3"]);
+        assert!(!releases[0].complete);
+        assert_eq!(releases[1].first_sequence, 5);
+        assert_eq!(releases[1].delivery_tags, [5]);
+        assert!(releases[1].complete);
+
+        assert!(resequencer.snapshot().is_empty());
+        assert!(resequencer.flush("a").is_none());
+    }
+
+    #[test]
+    fn discard_reports_the_sequence() {
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Batch, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        resequencer.push_sequence(part("a", 3, 2)).unwrap();
+
+        let discarded = resequencer.discard("a", Instant::now()).unwrap();
+        assert_eq!(discarded.reason, EvictionReason::Discarded);
+        assert_eq!(discarded.missing, [1, 3]);
+        assert_eq!(discarded.delivery_tags, [2]);
+        assert!(resequencer.discard("a", Instant::now()).is_none());
+    }
+}
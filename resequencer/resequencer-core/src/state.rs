@@ -0,0 +1,492 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io,
+    time::Instant,
+};
+
+use crate::{
+    resequencer::{
+        Admission, IncompleteSequence, OpenSequence, PushError, Pushed, Release, Resequencer,
+        Sequence, SequenceDump,
+    },
+    store::{StateStore, StoreEvent},
+};
+
+/// Why [`State::push_sequence`] did not push a part.
+#[derive(Debug)]
+pub enum StateError {
+    /// The [`Resequencer`] rejected the part.
+    Rejected(PushError),
+    /// The part could not be stored.
+    Store(io::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Rejected(e) => write!(f, "{}", e),
+            StateError::Store(e) => write!(f, "could not store part: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// A release that has been taken out of the [`Resequencer`], but is not recorded in the store yet.
+#[derive(Debug)]
+struct Outstanding {
+    first_sequence: u64,
+    last_sequence: u64,
+    complete: bool,
+    confirmed: bool,
+}
+
+/// The [`Resequencer`] together with the [`StateStore`] that persists what it holds.
+pub struct State {
+    resequencer: Resequencer,
+    store: Box<dyn StateStore>,
+    /// Releases and evictions that are being published, but have not been confirmed.
+    /// The store still holds their parts, which a compaction from the in-memory state would lose,
+    /// so it waits until there are none.
+    unconfirmed: usize,
+    /// Releases that could not be published, to be published again. Compaction waits for them too.
+    retries: Vec<Release>,
+    /// The releases of every sequence that are not recorded in the store yet, in the order they were taken.
+    /// A release is only recorded once every earlier release of its sequence is confirmed, as recording it
+    /// would drop the parts of those from the store.
+    outstanding: HashMap<String, VecDeque<Outstanding>>,
+    /// Sequences that are done with, but whose removal from the store waits for their outstanding releases.
+    removed: HashSet<String>,
+}
+
+impl State {
+    /// Restores the recovered `events` into `resequencer` and compacts `store` to match, returning the
+    /// releases that were ready, but had not been published before the process stopped.
+    pub fn recover(
+        mut resequencer: Resequencer,
+        mut store: Box<dyn StateStore>,
+        events: Vec<StoreEvent>,
+    ) -> io::Result<(State, Vec<Release>)> {
+        resequencer.restore(events);
+        store.compact(&resequencer.snapshot())?;
+        let releases = resequencer.take_ready();
+
+        let mut state = State {
+            resequencer,
+            store,
+            unconfirmed: releases.len(),
+            retries: Vec::new(),
+            outstanding: HashMap::new(),
+            removed: HashSet::new(),
+        };
+        for release in &releases {
+            state.track(release);
+        }
+        Ok((state, releases))
+    }
+
+    /// Whether a part is safe once [`State::push_sequence`] returns, so its message may be acknowledged right away.
+    pub fn is_durable(&self) -> bool {
+        self.store.is_durable()
+    }
+
+    /// Records the part in the store before handing it to the [`Resequencer`].
+    ///
+    /// Nothing is pushed if the part could not be stored. Parts that the [`Resequencer`] discards are not stored.
+    /// A release of a sequence whose earlier release waits to be retried is held back and retried along with it,
+    /// so the sequence is still published in order.
+    pub fn push_sequence(&mut self, new_sequence: Sequence) -> Result<Pushed, StateError> {
+        let admission = self
+            .resequencer
+            .admit(&new_sequence)
+            .map_err(StateError::Rejected)?;
+
+        if admission == Admission::Store {
+            self.store
+                .append(&StoreEvent::Part {
+                    sequence_id: new_sequence.sequence_id.clone(),
+                    num_sequences: new_sequence.num_sequences,
+                    sequence: new_sequence.sequence,
+                    payload: new_sequence.payload.clone(),
+                })
+                .map_err(StateError::Store)?;
+        }
+
+        let mut pushed = self
+            .resequencer
+            .push_sequence(new_sequence)
+            .map_err(StateError::Rejected)?;
+        if let Some(release) = &pushed.release {
+            self.track(release);
+            let held_back = self
+                .retries
+                .iter()
+                .any(|retry| retry.sequence_id == release.sequence_id);
+            if held_back {
+                self.retries.extend(pushed.release.take());
+            } else {
+                self.unconfirmed += 1;
+            }
+        }
+        Ok(pushed)
+    }
+
+    /// Records that the release has been published and confirmed by the broker.
+    ///
+    /// Until then, and until every earlier release of its sequence is confirmed too, the stored parts of the
+    /// release are replayed after a restart. Failing to record the release is not fatal, as its parts are only
+    /// published again after a restart.
+    pub fn confirm_release(&mut self, release: &Release) -> io::Result<()> {
+        self.unconfirmed -= 1;
+
+        let Some(outstanding) = self.outstanding.get_mut(&release.sequence_id) else {
+            return Ok(());
+        };
+        if let Some(confirmed) = outstanding.iter_mut().find(|outstanding| {
+            !outstanding.confirmed && outstanding.first_sequence == release.first_sequence
+        }) {
+            confirmed.confirmed = true;
+        }
+
+        let mut recorded = None;
+        while outstanding
+            .front()
+            .is_some_and(|outstanding| outstanding.confirmed)
+        {
+            recorded = outstanding.pop_front();
+        }
+        if outstanding.is_empty() {
+            self.outstanding.remove(&release.sequence_id);
+        }
+
+        let Some(recorded) = recorded else {
+            return Ok(());
+        };
+        // A sequence evicted in the meantime only waited for its releases to be removed
+        let evicted = !self.outstanding.contains_key(&release.sequence_id)
+            && self.removed.contains(&release.sequence_id);
+        if recorded.complete || evicted {
+            self.remove(&release.sequence_id)
+        } else {
+            self.store.append(&StoreEvent::Released {
+                sequence_id: release.sequence_id.clone(),
+                through: recorded.last_sequence,
+            })
+        }
+    }
+
+    /// Holds on to a release that could not be published, to be published again by [`State::take_retries`].
+    ///
+    /// Later releases of its sequence are held back until then, and its deliveries are left unacknowledged.
+    pub fn abandon_release(&mut self, release: Release) {
+        self.unconfirmed -= 1;
+        self.retries.push(release);
+    }
+
+    /// Takes the releases that could not be published, in the order they were taken out of the [`Resequencer`].
+    ///
+    /// Every release has to be confirmed or abandoned again like any other.
+    pub fn take_retries(&mut self) -> Vec<Release> {
+        self.unconfirmed += self.retries.len();
+        std::mem::take(&mut self.retries)
+    }
+
+    /// Evicts incomplete sequences from the [`Resequencer`].
+    pub fn evict(&mut self, now: Instant) -> Vec<IncompleteSequence> {
+        let evicted = self.resequencer.evict(now);
+        self.unconfirmed += evicted.len();
+        evicted
+    }
+
+    /// Describes every open sequence, oldest first.
+    pub fn open_sequences(&self, now: Instant) -> Vec<OpenSequence> {
+        self.resequencer.open_sequences(now)
+    }
+
+    /// Describes an open sequence along with the parts it holds.
+    pub fn dump(&self, sequence_id: &str, now: Instant) -> Option<SequenceDump> {
+        self.resequencer.dump(sequence_id, now)
+    }
+
+    /// Closes an open sequence without waiting for its missing parts, releasing the parts it holds.
+    ///
+    /// Every release has to be confirmed or abandoned like any other. A sequence that holds no parts is
+    /// removed from the store right away, which fails if its removal could not be recorded. It is closed
+    /// nonetheless, but open again after a restart.
+    pub fn flush(&mut self, sequence_id: &str) -> Option<io::Result<Vec<Release>>> {
+        let releases = self.resequencer.flush(sequence_id)?;
+        if releases.is_empty() {
+            return Some(self.remove(sequence_id).map(|()| releases));
+        }
+        for release in &releases {
+            self.track(release);
+        }
+        self.unconfirmed += releases.len();
+        Some(Ok(releases))
+    }
+
+    /// Removes an open sequence without releasing anything. Its report has to be confirmed like that of an eviction.
+    pub fn discard(&mut self, sequence_id: &str, now: Instant) -> Option<IncompleteSequence> {
+        let discarded = self.resequencer.discard(sequence_id, now)?;
+        self.unconfirmed += 1;
+        Some(discarded)
+    }
+
+    /// Records that the report of an evicted sequence has been confirmed by the broker.
+    ///
+    /// Until then the stored parts of the sequence are replayed after a restart, and evicted again. Failing to
+    /// record the removal is not fatal, as the sequence is only evicted again after a restart.
+    pub fn confirm_eviction(&mut self, incomplete: &IncompleteSequence) -> io::Result<()> {
+        self.unconfirmed -= 1;
+        self.remove(&incomplete.sequence_id)
+    }
+
+    /// Rewrites the store from the in-memory state once it has grown large enough, returning whether it did.
+    ///
+    /// A store that could not be compacted keeps growing, and is compacted again with the next call.
+    pub fn compact_if_needed(&mut self) -> io::Result<bool> {
+        if self.unconfirmed > 0 || !self.retries.is_empty() || !self.store.needs_compaction() {
+            return Ok(false);
+        }
+        self.store.compact(&self.resequencer.snapshot())?;
+        Ok(true)
+    }
+
+    /// Registers a release that has been taken out of the [`Resequencer`], to be recorded once confirmed.
+    fn track(&mut self, release: &Release) {
+        self.outstanding
+            .entry(release.sequence_id.clone())
+            .or_default()
+            .push_back(Outstanding {
+                first_sequence: release.first_sequence,
+                last_sequence: release.last_sequence(),
+                complete: release.complete,
+                confirmed: false,
+            });
+    }
+
+    /// Removes a sequence from the store, once none of its releases is outstanding anymore.
+    fn remove(&mut self, sequence_id: &str) -> io::Result<()> {
+        if self.outstanding.contains_key(sequence_id) {
+            self.removed.insert(sequence_id.to_string());
+            return Ok(());
+        }
+        self.removed.remove(sequence_id);
+        self.store.append(&StoreEvent::Removed {
+            sequence_id: sequence_id.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        resequencer::{DuplicatePolicy, EvictionPolicy, ReleaseMode},
+        store::{FileStore, Loaded},
+    };
+
+    fn resequencer() -> Resequencer {
+        let eviction = EvictionPolicy {
+            sequence_timeout: None,
+            max_open_sequences: None,
+        };
+        Resequencer::new(ReleaseMode::Streaming, eviction, DuplicatePolicy::KeepFirst)
+    }
+
+    fn part(sequence: u64) -> Sequence {
+        Sequence::new(vec![sequence as u8], 3, sequence, "a".to_string(), None)
+    }
+
+    fn open(path: &std::path::Path) -> (State, Vec<Release>) {
+        let mut store = FileStore::open(path).unwrap();
+        let events = store.load().unwrap().events;
+        State::recover(resequencer(), Box::new(store), events).unwrap()
+    }
+
+    #[test]
+    fn unconfirmed_releases_are_recovered_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+
+        let (mut state, ready) = open(&path);
+        assert!(ready.is_empty());
+        assert!(state.is_durable());
+        let released = state.push_sequence(part(1)).unwrap().release.unwrap();
+        state.confirm_release(&released).unwrap();
+        state.push_sequence(part(3)).unwrap();
+        let unpublished = state.push_sequence(part(2)).unwrap().release.unwrap();
+        state.abandon_release(unpublished);
+        drop(state);
+
+        // Part 1 was confirmed, while parts 2 and 3 were never published
+        let (mut state, ready) = open(&path);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].first_sequence, 2);
+        assert_eq!(ready[0].payloads, [vec![2], vec![3]]);
+        state.confirm_release(&ready[0]).unwrap();
+        drop(state);
+
+        let (_, ready) = open(&path);
+        assert!(ready.is_empty());
+    }
+
+    #[test]
+    fn abandoned_releases_are_retried_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+
+        let (mut state, _) = open(&path);
+        let abandoned = state.push_sequence(part(1)).unwrap().release.unwrap();
+        state.abandon_release(abandoned);
+        assert_eq!(state.unconfirmed, 0);
+        // Held back, so that it is not published ahead of part 1
+        assert!(state.push_sequence(part(2)).unwrap().release.is_none());
+
+        let retries = state.take_retries();
+        assert_eq!(retries.len(), 2);
+        assert_eq!(retries[0].payloads, [vec![1]]);
+        assert_eq!(retries[1].payloads, [vec![2]]);
+        for retry in &retries {
+            state.confirm_release(retry).unwrap();
+        }
+        assert_eq!(state.unconfirmed, 0);
+        assert!(state.take_retries().is_empty());
+        drop(state);
+
+        let (state, ready) = open(&path);
+        assert!(ready.is_empty());
+        assert_eq!(state.open_sequences(Instant::now())[0].received, [1, 2]);
+    }
+
+    #[test]
+    fn confirmed_releases_do_not_drop_the_parts_of_abandoned_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+
+        let (mut state, _) = open(&path);
+        // Both releases are in flight at once, and only the later one is confirmed
+        let abandoned = state.push_sequence(part(1)).unwrap().release.unwrap();
+        let confirmed = state.push_sequence(part(2)).unwrap().release.unwrap();
+        state.abandon_release(abandoned);
+        state.confirm_release(&confirmed).unwrap();
+        assert_eq!(state.unconfirmed, 0);
+        drop(state);
+
+        // Part 1 is recovered, along with part 2, as the latter was not recorded as released ahead of it
+        let (_, ready) = open(&path);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].first_sequence, 1);
+        assert_eq!(ready[0].payloads, [vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn evicted_sequences_are_removed_once_their_releases_are_confirmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+
+        let (mut state, _) = open(&path);
+        let abandoned = state.push_sequence(part(1)).unwrap().release.unwrap();
+        state.abandon_release(abandoned);
+        let discarded = state.discard("a", Instant::now()).unwrap();
+        state.confirm_eviction(&discarded).unwrap();
+        drop(state);
+
+        // The abandoned part outlives the eviction
+        let (mut state, ready) = open(&path);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].payloads, [vec![1]]);
+        let discarded = state.discard("a", Instant::now()).unwrap();
+        state.confirm_eviction(&discarded).unwrap();
+        state.confirm_release(&ready[0]).unwrap();
+        drop(state);
+
+        let (state, ready) = open(&path);
+        assert!(ready.is_empty());
+        assert!(state.open_sequences(Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn rejected_parts_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+
+        let (mut state, _) = open(&path);
+        state.push_sequence(part(1)).unwrap();
+        let conflicting = Sequence::new(vec![], 4, 2, "a".to_string(), None);
+        assert!(matches!(
+            state.push_sequence(conflicting),
+            Err(StateError::Rejected(PushError::NumSequencesConflict { .. }))
+        ));
+
+        let events = FileStore::open(&path).unwrap().load().unwrap().events;
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn flushed_sequences_are_removed_once_confirmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+
+        let (mut state, _) = open(&path);
+        state.push_sequence(part(2)).unwrap();
+        let releases = state.flush("a").unwrap().unwrap();
+        assert_eq!(releases.len(), 1);
+        drop(state);
+
+        // The flushed release was never confirmed, so its part is still held after a restart
+        let (mut state, _) = open(&path);
+        assert_eq!(state.open_sequences(Instant::now()).len(), 1);
+        let releases = state.flush("a").unwrap().unwrap();
+        state.confirm_release(&releases[0]).unwrap();
+        drop(state);
+
+        let (state, _) = open(&path);
+        assert!(state.open_sequences(Instant::now()).is_empty());
+    }
+
+    /// A store whose compaction fails, such as when the disk is full.
+    struct UncompactableStore;
+
+    impl StateStore for UncompactableStore {
+        fn append(&mut self, _event: &StoreEvent) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn load(&mut self) -> io::Result<Loaded> {
+            Ok(Loaded::default())
+        }
+
+        fn compact(&mut self, _events: &[StoreEvent]) -> io::Result<()> {
+            Err(io::Error::other("the disk is full"))
+        }
+
+        fn needs_compaction(&self) -> bool {
+            true
+        }
+
+        fn is_durable(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn failed_compactions_are_returned() {
+        let mut state = State {
+            resequencer: resequencer(),
+            store: Box::new(UncompactableStore),
+            unconfirmed: 0,
+            retries: Vec::new(),
+            outstanding: HashMap::new(),
+            removed: HashSet::new(),
+        };
+
+        let release = state.push_sequence(part(1)).unwrap().release.unwrap();
+        assert!(!state.compact_if_needed().unwrap());
+        state.confirm_release(&release).unwrap();
+        assert_eq!(
+            state.compact_if_needed().unwrap_err().to_string(),
+            "the disk is full"
+        );
+    }
+}
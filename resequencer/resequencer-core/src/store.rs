@@ -0,0 +1,279 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How many events the [`FileStore`] appends before it asks to be compacted.
+const COMPACT_AFTER_EVENTS: usize = 10_000;
+
+/// A change to the state of the resequencer, as recorded by a [`StateStore`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StoreEvent {
+    /// A sequence is open, with every part up to and including `released_through` published downstream.
+    /// Written in place of the events of a sequence, when the store is compacted.
+    Opened {
+        sequence_id: String,
+        num_sequences: u64,
+        released_through: u64,
+    },
+    /// A part was received.
+    Part {
+        sequence_id: String,
+        num_sequences: u64,
+        sequence: u64,
+        #[serde(with = "base64_payload")]
+        payload: Vec<u8>,
+    },
+    /// Every part up to and including `through` has been published downstream.
+    Released { sequence_id: String, through: u64 },
+    /// The sequence is done with, either because it was completed or evicted.
+    Removed { sequence_id: String },
+}
+
+impl StoreEvent {
+    pub fn sequence_id(&self) -> &str {
+        match self {
+            StoreEvent::Opened { sequence_id, .. }
+            | StoreEvent::Part { sequence_id, .. }
+            | StoreEvent::Released { sequence_id, .. }
+            | StoreEvent::Removed { sequence_id } => sequence_id,
+        }
+    }
+}
+
+/// Stores payloads as base64 strings, since they may be arbitrary bytes.
+pub(crate) mod base64_payload {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(payload: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(payload))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(D::Error::custom)
+    }
+}
+
+/// The events a [`StateStore`] read back.
+#[derive(Debug, Default, PartialEq)]
+pub struct Loaded {
+    pub events: Vec<StoreEvent>,
+    /// How many events could not be read and were skipped, such as the last one when the process crashed halfway
+    /// through appending it.
+    pub skipped: usize,
+}
+
+/// Persistence backend for the sequences held by the resequencer.
+///
+/// Events are appended as they happen and replayed in order on startup. A store that is durable lets the
+/// resequencer acknowledge a message as soon as its part has been appended, rather than holding on to the
+/// delivery until the sequence has been published.
+pub trait StateStore: Send {
+    /// Records `event`, returning once it would survive the process crashing.
+    fn append(&mut self, event: &StoreEvent) -> io::Result<()>;
+
+    /// Reads back every event recorded so far, in the order they were appended.
+    fn load(&mut self) -> io::Result<Loaded>;
+
+    /// Replaces everything recorded so far with `events`, which describe the same state.
+    fn compact(&mut self, events: &[StoreEvent]) -> io::Result<()>;
+
+    /// Whether the store has grown enough that it should be compacted.
+    fn needs_compaction(&self) -> bool {
+        false
+    }
+
+    /// Whether appended events survive a restart.
+    fn is_durable(&self) -> bool;
+}
+
+/// Keeps nothing, for when the state only lives as long as the process.
+#[derive(Debug, Default)]
+pub struct MemoryStore;
+
+impl StateStore for MemoryStore {
+    fn append(&mut self, _event: &StoreEvent) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Loaded> {
+        Ok(Loaded::default())
+    }
+
+    fn compact(&mut self, _events: &[StoreEvent]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn is_durable(&self) -> bool {
+        false
+    }
+}
+
+/// Appends events as JSON lines to a file, syncing it to disk after every event.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    appended: usize,
+}
+
+impl FileStore {
+    /// Opens the file at `path` for appending, creating it if it does not exist.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<FileStore> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(FileStore {
+            path,
+            file,
+            appended: 0,
+        })
+    }
+}
+
+impl StateStore for FileStore {
+    fn append(&mut self, event: &StoreEvent) -> io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.appended += 1;
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Loaded> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        let mut loaded = Loaded::default();
+        let mut lines = reader.lines().peekable();
+        while let Some(line) = lines.next() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(event) => loaded.events.push(event),
+                // The process may have crashed halfway through appending the last event
+                Err(_) if lines.peek().is_none() => loaded.skipped += 1,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+        Ok(loaded)
+    }
+
+    fn compact(&mut self, events: &[StoreEvent]) -> io::Result<()> {
+        // Write the compacted events next to the log, then swap it in, so a crash leaves either file intact
+        let mut compacted_path = self.path.clone().into_os_string();
+        compacted_path.push(".compact");
+        let compacted_path = PathBuf::from(compacted_path);
+
+        let mut compacted = File::create(&compacted_path)?;
+        for event in events {
+            let mut line = serde_json::to_vec(event)?;
+            line.push(b'\n');
+            compacted.write_all(&line)?;
+        }
+        compacted.sync_all()?;
+        fs::rename(&compacted_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.appended = 0;
+        Ok(())
+    }
+
+    fn needs_compaction(&self) -> bool {
+        self.appended >= COMPACT_AFTER_EVENTS
+    }
+
+    fn is_durable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(sequence: u64) -> StoreEvent {
+        StoreEvent::Part {
+            sequence_id: "a".to_string(),
+            num_sequences: 3,
+            sequence,
+            payload: vec![0, 255, sequence as u8],
+        }
+    }
+
+    #[test]
+    fn file_store_loads_what_was_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+        let events = vec![
+            part(1),
+            StoreEvent::Released {
+                sequence_id: "a".to_string(),
+                through: 1,
+            },
+            StoreEvent::Removed {
+                sequence_id: "a".to_string(),
+            },
+        ];
+
+        let mut store = FileStore::open(&path).unwrap();
+        for event in &events {
+            store.append(event).unwrap();
+        }
+        drop(store);
+
+        assert_eq!(
+            FileStore::open(&path).unwrap().load().unwrap(),
+            Loaded { events, skipped: 0 }
+        );
+    }
+
+    #[test]
+    fn file_store_ignores_a_truncated_last_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+        let mut store = FileStore::open(&path).unwrap();
+        store.append(&part(1)).unwrap();
+        store.file.write_all(br#"{"event":"part","seq"#).unwrap();
+
+        assert_eq!(
+            store.load().unwrap(),
+            Loaded {
+                events: vec![part(1)],
+                skipped: 1
+            }
+        );
+    }
+
+    #[test]
+    fn file_store_fails_on_a_corrupt_event_before_the_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+        fs::write(&path, "garbage\n").unwrap();
+        let mut store = FileStore::open(&path).unwrap();
+        store.append(&part(1)).unwrap();
+
+        let error = store.load().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compaction_replaces_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.jsonl");
+        let mut store = FileStore::open(&path).unwrap();
+        for sequence in 1..=3 {
+            store.append(&part(sequence)).unwrap();
+        }
+
+        store.compact(&[part(2)]).unwrap();
+        store.append(&part(3)).unwrap();
+
+        assert_eq!(store.load().unwrap().events, [part(2), part(3)]);
+        assert!(!dir.path().join("state.jsonl.compact").exists());
+        assert!(!store.needs_compaction());
+    }
+}
@@ -0,0 +1,207 @@
+//! Property tests of the resequencer, pushing sequences whose parts arrive in arbitrary orders.
+
+use std::time::{Duration, Instant};
+
+use proptest::prelude::*;
+use resequencer_core::resequencer::{
+    DuplicatePolicy, EvictionPolicy, EvictionReason, ReleaseMode, Resequencer, Sequence,
+};
+
+const NO_EVICTION: EvictionPolicy = EvictionPolicy {
+    sequence_timeout: None,
+    max_open_sequences: None,
+};
+
+fn mode() -> impl Strategy<Value = ReleaseMode> {
+    prop_oneof![Just(ReleaseMode::Batch), Just(ReleaseMode::Streaming)]
+}
+
+/// The `sequence` numbers of a sequence of 1 to 30 parts, in a random order of arrival.
+fn arrivals() -> impl Strategy<Value = Vec<u64>> {
+    (1..=30u64)
+        .prop_flat_map(|num_sequences| Just((1..=num_sequences).collect::<Vec<_>>()).prop_shuffle())
+}
+
+fn part(sequence_id: &str, num_sequences: u64, sequence: u64) -> Sequence {
+    Sequence::new(
+        sequence.to_be_bytes().to_vec(),
+        num_sequences,
+        sequence,
+        sequence_id.to_string(),
+        Some(sequence),
+    )
+}
+
+/// Pushes the parts, checking every released payload, and returns the released `sequence` numbers in order
+/// along with whether they completed their sequence.
+fn push_all(resequencer: &mut Resequencer, parts: Vec<Sequence>) -> Vec<(String, u64, bool)> {
+    let mut released = Vec::new();
+    for part in parts {
+        let pushed = resequencer.push_sequence(part).unwrap();
+        if let Some(release) = pushed.release {
+            for (offset, payload) in release.payloads.iter().enumerate() {
+                assert_eq!(
+                    payload,
+                    &(release.first_sequence + offset as u64).to_be_bytes()
+                );
+            }
+            released.extend(
+                (release.first_sequence..=release.last_sequence()).map(|sequence| {
+                    (
+                        release.sequence_id.clone(),
+                        sequence,
+                        release.complete && sequence == release.last_sequence(),
+                    )
+                }),
+            );
+        }
+    }
+    released
+}
+
+proptest! {
+    #[test]
+    fn any_arrival_order_is_released_in_order(mode in mode(), arrivals in arrivals()) {
+        let num_sequences = arrivals.len() as u64;
+        let mut resequencer = Resequencer::new(mode, NO_EVICTION, DuplicatePolicy::KeepFirst);
+
+        let parts = arrivals.iter().map(|&sequence| part("a", num_sequences, sequence)).collect();
+        let released = push_all(&mut resequencer, parts);
+
+        let sequences: Vec<u64> = released.iter().map(|(_, sequence, _)| *sequence).collect();
+        prop_assert_eq!(sequences, (1..=num_sequences).collect::<Vec<_>>());
+        prop_assert!(released.last().unwrap().2);
+        prop_assert!(resequencer.snapshot().is_empty());
+    }
+
+    #[test]
+    fn interleaved_sequences_are_each_released_in_order(
+        mode in mode(),
+        a in arrivals(),
+        b in arrivals(),
+        interleaving in proptest::collection::vec(any::<bool>(), 60),
+    ) {
+        let mut resequencer = Resequencer::new(mode, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        let (num_a, num_b) = (a.len() as u64, b.len() as u64);
+        let mut a = a.into_iter().map(|sequence| part("a", num_a, sequence)).peekable();
+        let mut b = b.into_iter().map(|sequence| part("b", num_b, sequence)).peekable();
+
+        let mut parts = Vec::new();
+        for take_a in interleaving.into_iter().chain(std::iter::repeat(true)) {
+            match (take_a, a.peek().is_some(), b.peek().is_some()) {
+                (_, false, false) => break,
+                (true, true, _) | (false, true, false) => parts.push(a.next().unwrap()),
+                _ => parts.push(b.next().unwrap()),
+            }
+        }
+        let released = push_all(&mut resequencer, parts);
+
+        for (sequence_id, num_sequences) in [("a", num_a), ("b", num_b)] {
+            let sequences: Vec<u64> = released
+                .iter()
+                .filter(|(id, _, _)| id == sequence_id)
+                .map(|(_, sequence, _)| *sequence)
+                .collect();
+            prop_assert_eq!(sequences, (1..=num_sequences).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn duplicates_are_discarded_under_keep_first(
+        mode in mode(),
+        arrivals in arrivals(),
+        duplicates in proptest::collection::vec(any::<prop::sample::Index>(), 0..20),
+    ) {
+        let num_sequences = arrivals.len() as u64;
+        let mut resequencer = Resequencer::new(mode, NO_EVICTION, DuplicatePolicy::KeepFirst);
+
+        // Redeliver random parts right after they arrived, with a payload that must never be released.
+        // A completed sequence is forgotten, so the part that completes it is not redelivered.
+        let mut parts = Vec::new();
+        for (position, &sequence) in arrivals.iter().enumerate() {
+            parts.push(part("a", num_sequences, sequence));
+            let last = position + 1 == arrivals.len();
+            if !last && duplicates.iter().any(|index| index.index(arrivals.len()) == position) {
+                let mut duplicate = part("a", num_sequences, sequence);
+                duplicate.payload = b"duplicate".to_vec();
+                parts.push(duplicate);
+            }
+        }
+        let released = push_all(&mut resequencer, parts);
+
+        let sequences: Vec<u64> = released.iter().map(|(_, sequence, _)| *sequence).collect();
+        prop_assert_eq!(sequences, (1..=num_sequences).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn gaps_are_reported_when_evicted(
+        arrivals in arrivals(),
+        missing in proptest::collection::vec(any::<prop::sample::Index>(), 1..10),
+    ) {
+        let num_sequences = arrivals.len() as u64;
+        let missing: Vec<u64> = {
+            let mut missing: Vec<u64> = missing
+                .iter()
+                .map(|index| index.index(arrivals.len()) as u64 + 1)
+                .collect();
+            missing.sort_unstable();
+            missing.dedup();
+            missing
+        };
+        // A sequence is only open once one of its parts has arrived
+        prop_assume!(missing.len() < arrivals.len());
+        let eviction = EvictionPolicy {
+            sequence_timeout: Some(Duration::from_secs(60)),
+            max_open_sequences: None,
+        };
+        let mut resequencer =
+            Resequencer::new(ReleaseMode::Streaming, eviction, DuplicatePolicy::KeepFirst);
+
+        let parts = arrivals
+            .iter()
+            .filter(|sequence| !missing.contains(sequence))
+            .map(|&sequence| part("a", num_sequences, sequence))
+            .collect();
+        let released = push_all(&mut resequencer, parts);
+
+        // Only the parts before the first gap can have been released
+        let released: Vec<u64> = released.iter().map(|(_, sequence, _)| *sequence).collect();
+        prop_assert_eq!(released, (1..missing[0]).collect::<Vec<_>>());
+
+        let evicted = resequencer.evict(Instant::now() + Duration::from_secs(61));
+        prop_assert_eq!(evicted.len(), 1);
+        let incomplete = &evicted[0];
+        prop_assert_eq!(incomplete.reason, EvictionReason::Timeout);
+        prop_assert_eq!(&incomplete.missing, &missing);
+        let received: Vec<u64> = (1..=num_sequences).filter(|sequence| !missing.contains(sequence)).collect();
+        prop_assert_eq!(&incomplete.received, &received);
+        // The held parts are those after the first gap
+        let mut held = incomplete.delivery_tags.clone();
+        held.sort_unstable();
+        let expected: Vec<u64> = received.into_iter().filter(|&sequence| sequence > missing[0]).collect();
+        prop_assert_eq!(held, expected);
+    }
+
+    #[test]
+    fn snapshot_and_restore_release_the_same_payloads(
+        mode in mode(),
+        arrivals in arrivals(),
+        split in any::<prop::sample::Index>(),
+    ) {
+        let num_sequences = arrivals.len() as u64;
+        let split = split.index(arrivals.len());
+        let mut before = Resequencer::new(mode, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        let parts = arrivals[..split].iter().map(|&sequence| part("a", num_sequences, sequence)).collect();
+        let mut released = push_all(&mut before, parts);
+
+        // Restart halfway through, carrying over only what the snapshot describes
+        let mut after = Resequencer::new(mode, NO_EVICTION, DuplicatePolicy::KeepFirst);
+        after.restore(before.snapshot());
+        prop_assert!(after.take_ready().is_empty());
+        let parts = arrivals[split..].iter().map(|&sequence| part("a", num_sequences, sequence)).collect();
+        released.extend(push_all(&mut after, parts));
+
+        let sequences: Vec<u64> = released.iter().map(|(_, sequence, _)| *sequence).collect();
+        prop_assert_eq!(sequences, (1..=num_sequences).collect::<Vec<_>>());
+    }
+}
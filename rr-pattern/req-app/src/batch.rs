@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A line of the requests of a batch.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BatchRequest {
+    /// Tells the reply to the request apart. The line number of the request if missing.
+    pub id: Option<Value>,
+    /// Overrides the routing key the requests are sent with.
+    pub routing_key: Option<String>,
+    pub body: Value,
+}
+
+/// A line of the replies of a batch, carrying either the reply or why there is none.
+#[derive(Debug, Serialize)]
+pub struct BatchReply {
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BatchError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchError {
+    pub reason: String,
+    pub message: String,
+}
+
+impl BatchReply {
+    pub fn replied(id: Value, reply: Value) -> BatchReply {
+        BatchReply {
+            id,
+            reply: Some(reply),
+            error: None,
+        }
+    }
+
+    pub fn failed(id: Value, reason: &str, message: String) -> BatchReply {
+        BatchReply {
+            id,
+            reply: None,
+            error: Some(BatchError {
+                reason: reason.to_string(),
+                message,
+            }),
+        }
+    }
+}
+
+/// Parses a line of the requests, numbered from 1.
+pub fn parse_line(number: usize, line: &str) -> Result<BatchRequest, BatchReply> {
+    match serde_json::from_str::<BatchRequest>(line) {
+        Ok(mut request) => {
+            request.id.get_or_insert_with(|| Value::from(number));
+            Ok(request)
+        }
+        Err(e) => Err(BatchReply::failed(
+            Value::from(number),
+            "invalid_line",
+            e.to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn requests_are_numbered_by_their_line() {
+        let request = parse_line(3, r#"{"body": "hello"}"#).unwrap();
+        assert_eq!(
+            request,
+            BatchRequest {
+                id: Some(json!(3)),
+                routing_key: None,
+                body: json!("hello"),
+            }
+        );
+
+        let request =
+            parse_line(3, r#"{"id": "a", "routing_key": "math.sum", "body": [1]}"#).unwrap();
+        assert_eq!(request.id, Some(json!("a")));
+        assert_eq!(request.routing_key.as_deref(), Some("math.sum"));
+    }
+
+    #[test]
+    fn invalid_lines_are_replied_to_with_an_error() {
+        let reply = parse_line(2, r#"{"bdy": 1}"#).unwrap_err();
+        let reply = serde_json::to_value(reply).unwrap();
+        assert_eq!(reply["id"], json!(2));
+        assert_eq!(reply["error"]["reason"], json!("invalid_line"));
+        assert!(reply.get("reply").is_none());
+    }
+
+    #[test]
+    fn replies_carry_either_the_reply_or_the_error() {
+        let reply = BatchReply::replied(json!("a"), json!(6.0));
+        assert_eq!(
+            serde_json::to_value(reply).unwrap(),
+            json!({"id": "a", "reply": 6.0})
+        );
+        let reply = BatchReply::failed(json!(1), "timeout", String::from("too slow"));
+        assert_eq!(
+            serde_json::to_value(reply).unwrap(),
+            json!({"id": 1, "error": {"reason": "timeout", "message": "too slow"}})
+        );
+    }
+}
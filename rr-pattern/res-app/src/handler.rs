@@ -0,0 +1,257 @@
+use std::{fmt, future::Future, pin::Pin};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+/// Why a request could not be answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerError {
+    /// A short machine readable identifier of the error.
+    pub code: &'static str,
+    pub message: String,
+    /// Whether processing the request again may succeed, such as after a dependency was unavailable.
+    pub retryable: bool,
+}
+
+impl HandlerError {
+    /// A request that is malformed, and so fails however often it is retried.
+    pub fn invalid(message: impl Into<String>) -> HandlerError {
+        HandlerError {
+            code: "invalid_request",
+            message: message.into(),
+            retryable: false,
+        }
+    }
+
+    /// The error reply sent to the client, once the request is given up on.
+    pub fn envelope(&self, attempts: u32) -> String {
+        json!({
+            "error": {
+                "code": self.code,
+                "message": self.message,
+                "retryable": self.retryable,
+                "attempts": attempts,
+            }
+        })
+        .to_string()
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for HandlerError {}
+
+/// The reply of a handler, encoded as JSON.
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Result<String, HandlerError>> + Send>>;
+
+type BoxedHandler = Box<dyn Fn(&[u8]) -> HandlerFuture + Send + Sync>;
+
+/// The handlers of the operations a responder serves, by the routing key of their requests on `rr-exchange`.
+///
+/// A routing key pattern is matched as by a topic exchange: `*` stands for exactly one word and `#` for zero or more.
+/// A request is answered by the first registered handler whose pattern matches its routing key.
+#[derive(Default)]
+pub struct Registry {
+    handlers: Vec<(String, BoxedHandler)>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Registers a handler for the requests whose routing key matches `pattern`.
+    ///
+    /// Requests are decoded from JSON into `Req`, and responses encoded as JSON. A request that is no JSON is taken as
+    /// a JSON string of its text, so plain text requests reach handlers of strings.
+    pub fn register<Req, Res, F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Registry
+    where
+        Req: DeserializeOwned,
+        Res: Serialize,
+        F: Fn(Req) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Res, HandlerError>> + Send + 'static,
+    {
+        let handler = move |body: &[u8]| -> HandlerFuture {
+            let request = match decode::<Req>(body) {
+                Ok(request) => request,
+                Err(e) => return Box::pin(async move { Err(e) }),
+            };
+            let response = handler(request);
+            Box::pin(async move {
+                let response = response.await?;
+                serde_json::to_string(&response).map_err(|e| HandlerError {
+                    code: "internal_error",
+                    message: format!("could not encode the response: {}", e),
+                    retryable: false,
+                })
+            })
+        };
+        self.handlers.push((pattern.to_string(), Box::new(handler)));
+        self
+    }
+
+    /// The routing key patterns of the registered handlers, which the request queue is bound with.
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.handlers.iter().map(|(pattern, _)| pattern.as_str())
+    }
+
+    /// Answers a request with the handler registered for its routing key.
+    pub fn handle(&self, routing_key: &str, request: &[u8]) -> HandlerFuture {
+        match self
+            .handlers
+            .iter()
+            .find(|(pattern, _)| matches(pattern, routing_key))
+        {
+            Some((_, handler)) => handler(request),
+            None => {
+                let error = HandlerError {
+                    code: "unknown_operation",
+                    message: format!("no handler is registered for '{}'", routing_key),
+                    retryable: false,
+                };
+                Box::pin(async move { Err(error) })
+            }
+        }
+    }
+}
+
+fn decode<Req: DeserializeOwned>(body: &[u8]) -> Result<Req, HandlerError> {
+    match serde_json::from_slice::<Req>(body) {
+        Ok(request) => Ok(request),
+        Err(e) if serde_json::from_slice::<Value>(body).is_err() => {
+            let text = Value::String(String::from_utf8_lossy(body).into_owned());
+            serde_json::from_value(text).map_err(|_| HandlerError::invalid(e.to_string()))
+        }
+        Err(e) => Err(HandlerError::invalid(e.to_string())),
+    }
+}
+
+/// Whether a routing key matches a pattern of a topic exchange.
+pub fn matches(pattern: &str, routing_key: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let routing_key: Vec<&str> = routing_key.split('.').collect();
+    matches_words(&pattern, &routing_key)
+}
+
+fn matches_words(pattern: &[&str], words: &[&str]) -> bool {
+    match (pattern.first(), words.first()) {
+        (None, None) => true,
+        // `#` either ends here or swallows one more word
+        (Some(&"#"), _) => {
+            matches_words(&pattern[1..], words)
+                || (!words.is_empty() && matches_words(pattern, &words[1..]))
+        }
+        (Some(&"*"), Some(_)) => matches_words(&pattern[1..], &words[1..]),
+        (Some(expected), Some(word)) => {
+            expected == word && matches_words(&pattern[1..], &words[1..])
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match_like_a_topic_exchange() {
+        assert!(matches("rr-request", "rr-request"));
+        assert!(!matches("rr-request", "rr-request.other"));
+        assert!(matches("math.*", "math.sum"));
+        assert!(!matches("math.*", "math"));
+        assert!(!matches("math.*", "math.sum.all"));
+        assert!(matches("math.#", "math"));
+        assert!(matches("math.#", "math.sum.all"));
+        assert!(matches("#.sum", "math.sum"));
+        assert!(matches("#", "anything.at.all"));
+        assert!(!matches("math.#.all", "text.sum.all"));
+    }
+
+    fn registry() -> Registry {
+        let mut registry = Registry::new();
+        registry
+            .register("math.sum", |numbers: Vec<f64>| async move {
+                Ok(numbers.iter().sum::<f64>())
+            })
+            .register(
+                "text.*",
+                |text: String| async move { Ok(text.to_uppercase()) },
+            )
+            .register("#", |_: Value| async move { Ok("fallback") });
+        registry
+    }
+
+    #[tokio::test]
+    async fn requests_reach_the_first_matching_handler() {
+        let registry = registry();
+        assert_eq!(
+            registry.handle("math.sum", b"[1, 2.5]").await.unwrap(),
+            "3.5"
+        );
+        assert_eq!(
+            registry.handle("text.upper", br#""abc""#).await.unwrap(),
+            r#""ABC""#
+        );
+        assert_eq!(
+            registry.handle("other", b"null").await.unwrap(),
+            r#""fallback""#
+        );
+        assert_eq!(
+            registry.patterns().collect::<Vec<_>>(),
+            vec!["math.sum", "text.*", "#"]
+        );
+    }
+
+    #[tokio::test]
+    async fn plain_text_reaches_handlers_of_strings() {
+        let registry = registry();
+        assert_eq!(
+            registry.handle("text.upper", b"abc").await.unwrap(),
+            r#""ABC""#
+        );
+        assert_eq!(
+            registry.handle("math.sum", b"abc").await.unwrap_err().code,
+            "invalid_request"
+        );
+        assert_eq!(
+            registry
+                .handle("math.sum", br#"{"a": 1}"#)
+                .await
+                .unwrap_err()
+                .code,
+            "invalid_request"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_operations_are_not_retried() {
+        let error = Registry::new().handle("math.sum", b"[]").await.unwrap_err();
+        assert_eq!(error.code, "unknown_operation");
+        assert!(!error.retryable);
+    }
+
+    #[test]
+    fn envelope_carries_the_error_and_attempts() {
+        let error = HandlerError {
+            code: "unavailable",
+            message: String::from("try again"),
+            retryable: true,
+        };
+        let envelope: serde_json::Value = serde_json::from_str(&error.envelope(4)).unwrap();
+        assert_eq!(
+            envelope,
+            json!({
+                "error": {
+                    "code": "unavailable",
+                    "message": "try again",
+                    "retryable": true,
+                    "attempts": 4,
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use crate::handler::{HandlerError, Registry};
+
+/// The operations this responder serves.
+pub fn registry() -> Registry {
+    let mut registry = Registry::new();
+    registry
+        // What every request-reply client sends, answered as it always was
+        .register("rr-request", echo)
+        .register("echo.#", echo)
+        .register("math.sum", sum);
+    registry
+}
+
+async fn echo(request: String) -> Result<String, HandlerError> {
+    if request.trim().is_empty() {
+        return Err(HandlerError::invalid("the request is empty"));
+    }
+    Ok(format!("Responding to: {}", request))
+}
+
+async fn sum(numbers: Vec<f64>) -> Result<f64, HandlerError> {
+    Ok(numbers.iter().sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn echo_answers_every_request_but_empty_ones() {
+        let registry = registry();
+        assert_eq!(
+            registry.handle("rr-request", b"hello").await.unwrap(),
+            r#""Responding to: hello""#
+        );
+        assert_eq!(
+            registry.handle("echo.a.b", br#""hi""#).await.unwrap(),
+            r#""Responding to: hi""#
+        );
+        assert_eq!(
+            registry
+                .handle("rr-request", b" \n")
+                .await
+                .unwrap_err()
+                .code,
+            "invalid_request"
+        );
+    }
+
+    #[tokio::test]
+    async fn sum_adds_the_numbers() {
+        assert_eq!(
+            registry().handle("math.sum", b"[1, 2, 3]").await.unwrap(),
+            "6.0"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use lapin::types::{AMQPValue, FieldTable};
+
+use crate::handler::HandlerError;
+
+/// Header a requeued request counts its previous attempts with.
+pub const RETRY_HEADER: &str = "x-retry-count";
+
+/// What becomes of a request its handler failed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The request is published again, having been retried this many times then.
+    Retry(u32),
+    /// The request is given up on after this many attempts, and the client receives the error.
+    GiveUp(u32),
+}
+
+/// Decides whether a failed request is retried, given how often it was retried before.
+pub fn on_failure(retryable: bool, retries: u32, max_retries: u32) -> Failure {
+    if retryable && retries < max_retries {
+        Failure::Retry(retries + 1)
+    } else {
+        Failure::GiveUp(retries + 1)
+    }
+}
+
+/// What to publish for a request its handler failed on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// A copy of the request is published again with these headers, which count the retry.
+    Retry { headers: FieldTable, retries: u32 },
+    /// The client is replied to with this error envelope.
+    GiveUp { envelope: String, attempts: u32 },
+}
+
+/// Decides what becomes of a request with the `headers` that its handler failed on with `error`.
+pub fn after_failure(
+    error: &HandlerError,
+    headers: &Option<FieldTable>,
+    max_retries: u32,
+) -> Outcome {
+    match on_failure(
+        error.retryable,
+        retry_count(headers, max_retries),
+        max_retries,
+    ) {
+        Failure::Retry(retries) => Outcome::Retry {
+            headers: with_retry_count(headers, retries),
+            retries,
+        },
+        Failure::GiveUp(attempts) => Outcome::GiveUp {
+            envelope: error.envelope(attempts),
+            attempts,
+        },
+    }
+}
+
+/// Reads how often a request was retried before. Requests without the header are on their first attempt.
+///
+/// A count that cannot be read, such as a negative one, counts as `max_retries`, so that the request is given up on
+/// rather than retried with a fresh budget forever.
+pub fn retry_count(headers: &Option<FieldTable>, max_retries: u32) -> u32 {
+    let value = headers
+        .as_ref()
+        .and_then(|headers| headers.inner().get(RETRY_HEADER).cloned());
+    let count = match value {
+        None => return 0,
+        Some(AMQPValue::LongUInt(count)) => Some(count),
+        Some(AMQPValue::ShortUInt(count)) => Some(u32::from(count)),
+        Some(AMQPValue::ShortShortUInt(count)) => Some(u32::from(count)),
+        Some(AMQPValue::LongLongInt(count)) => u32::try_from(count).ok(),
+        Some(AMQPValue::LongInt(count)) => u32::try_from(count).ok(),
+        Some(AMQPValue::ShortInt(count)) => u32::try_from(count).ok(),
+        Some(AMQPValue::ShortShortInt(count)) => u32::try_from(count).ok(),
+        Some(_) => None,
+    };
+    count.unwrap_or(max_retries)
+}
+
+/// The headers of a request with its retry count set, keeping every other header.
+pub fn with_retry_count(headers: &Option<FieldTable>, retries: u32) -> FieldTable {
+    let mut headers = headers.clone().unwrap_or_default();
+    headers.insert(RETRY_HEADER.into(), AMQPValue::LongUInt(retries));
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::Registry;
+
+    #[test]
+    fn retries_are_bounded() {
+        assert_eq!(on_failure(true, 0, 2), Failure::Retry(1));
+        assert_eq!(on_failure(true, 1, 2), Failure::Retry(2));
+        assert_eq!(on_failure(true, 2, 2), Failure::GiveUp(3));
+    }
+
+    #[test]
+    fn permanent_failures_are_not_retried() {
+        assert_eq!(on_failure(false, 0, 2), Failure::GiveUp(1));
+    }
+
+    #[test]
+    fn retry_count_round_trips_through_the_headers() {
+        let mut original = FieldTable::default();
+        original.insert("other".into(), AMQPValue::Boolean(true));
+        let original = Some(original);
+        assert_eq!(retry_count(&original, 5), 0);
+        assert_eq!(retry_count(&None, 5), 0);
+
+        let headers = with_retry_count(&original, 3);
+        assert!(headers.contains_key("other"));
+        assert_eq!(retry_count(&Some(headers), 5), 3);
+    }
+
+    #[test]
+    fn unreadable_retry_counts_exhaust_the_retries() {
+        for value in [
+            AMQPValue::LongInt(-1),
+            AMQPValue::LongString("2".into()),
+            AMQPValue::Boolean(true),
+        ] {
+            let mut headers = FieldTable::default();
+            headers.insert(RETRY_HEADER.into(), value);
+            let retries = retry_count(&Some(headers), 5);
+            assert_eq!(retries, 5);
+            assert_eq!(on_failure(true, retries, 5), Failure::GiveUp(6));
+        }
+    }
+
+    #[tokio::test]
+    async fn retryable_failures_are_retried_until_the_client_receives_the_error() {
+        let mut registry = Registry::new();
+        registry.register("flaky", |_: String| async move {
+            Err::<String, _>(HandlerError {
+                code: "unavailable",
+                message: String::from("the downstream service did not answer"),
+                retryable: true,
+            })
+        });
+
+        // Every retry is a copy of the request, carrying the headers of the previous attempt
+        let mut headers = None;
+        for expected in 1..=2 {
+            let error = registry.handle("flaky", b"request").await.unwrap_err();
+            match after_failure(&error, &headers, 2) {
+                Outcome::Retry {
+                    headers: retried,
+                    retries,
+                } => {
+                    assert_eq!(retries, expected);
+                    assert_eq!(retry_count(&Some(retried.clone()), 2), expected);
+                    headers = Some(retried);
+                }
+                other => panic!("unexpected outcome: {:?}", other),
+            }
+        }
+
+        let error = registry.handle("flaky", b"request").await.unwrap_err();
+        match after_failure(&error, &headers, 2) {
+            Outcome::GiveUp { envelope, attempts } => {
+                assert_eq!(attempts, 3);
+                let envelope: serde_json::Value = serde_json::from_str(&envelope).unwrap();
+                assert_eq!(envelope["error"]["code"], "unavailable");
+                assert_eq!(envelope["error"]["attempts"], 3);
+            }
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::{future::Future, time::Duration};
+
+use lapin::{
+    message::DeliveryResult,
+    options::{
+        BasicConsumeOptions, BasicPublishOptions, ConfirmSelectOptions, QueueDeclareOptions,
+    },
+    publisher_confirm::Confirmation,
+    types::FieldTable,
+    BasicProperties, Channel, Connection, ConnectionProperties,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    error::RpcError,
+    pending::{Pending, PendingCall, Reply},
+};
+
+/// Message type of the replies carrying an error envelope instead of a response.
+const ERROR_REPLY: &str = "error";
+
+/// Calls remote procedures over RabbitMQ and waits for their replies.
+///
+/// The client is cheap to share behind an `Arc`: every call registers itself by a correlation id of its own, so any
+/// number of calls can be in flight at once over the same connection and reply queue. The reply queue is declared and
+/// consumed once, when connecting, and its consumer hands every reply to the call waiting for it.
+pub struct RpcClient {
+    // Kept so the connection outlives the channel it carries
+    _connection: Connection,
+    channel: Channel,
+    reply_queue: String,
+    pending: Pending,
+}
+
+impl RpcClient {
+    /// Connects to the broker and starts consuming the reply queue, which lives as long as the client.
+    pub async fn connect(uri: &str) -> Result<RpcClient, RpcError> {
+        let options = ConnectionProperties::default()
+            .with_connection_name("rpc_client_connection".to_string().into())
+            .with_executor(tokio_executor_trait::Tokio::current())
+            .with_reactor(tokio_reactor_trait::Tokio);
+
+        let connection = Connection::connect(uri, options)
+            .await
+            .map_err(RpcError::Connection)?;
+        let channel = connection
+            .create_channel()
+            .await
+            .map_err(RpcError::Connection)?;
+
+        // Every request is confirmed by the broker, which also reports the requests no queue is bound for
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .map_err(RpcError::Connection)?;
+
+        // The replies to every call arrive on a single queue, named by the broker and deleted with the connection
+        let reply_queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..QueueDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(RpcError::Connection)?;
+        let reply_queue = reply_queue.name().as_str().to_string();
+
+        let replies = channel
+            .basic_consume(
+                &reply_queue,
+                "rpc-client-replies",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..BasicConsumeOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(RpcError::Connection)?;
+
+        let pending = Pending::default();
+        replies.set_delegate({
+            let pending = pending.clone();
+            move |delivery: DeliveryResult| {
+                let pending = pending.clone();
+                async move {
+                    let delivery = match delivery {
+                        Ok(Some(delivery)) => delivery,
+                        // The consumer got canceled, so no further reply can arrive
+                        Ok(None) => {
+                            pending.disconnect();
+                            return;
+                        }
+                        // Carries the error and is always followed by Ok(None)
+                        Err(e) => {
+                            println!("[Error] Failed to consume reply: {}", e);
+                            pending.disconnect();
+                            return;
+                        }
+                    };
+
+                    let correlation_id = match delivery.properties.correlation_id() {
+                        Some(correlation_id) => correlation_id.as_str().to_string(),
+                        None => {
+                            println!("[Warning] Dropping reply without a correlation id");
+                            return;
+                        }
+                    };
+                    let reply = Reply {
+                        content_type: delivery
+                            .properties
+                            .content_type()
+                            .as_ref()
+                            .map(|content_type| content_type.as_str().to_string()),
+                        kind: delivery
+                            .properties
+                            .kind()
+                            .as_ref()
+                            .map(|kind| kind.as_str().to_string()),
+                        payload: delivery.data,
+                    };
+                    // The call may have timed out or been cancelled in the meantime
+                    if !pending.resolve(&correlation_id, reply) {
+                        println!(
+                            "[Warning] Dropping reply to '{}', which nobody waits for anymore",
+                            correlation_id
+                        );
+                    }
+                }
+            }
+        });
+
+        Ok(RpcClient {
+            _connection: connection,
+            channel,
+            reply_queue,
+            pending,
+        })
+    }
+
+    /// The channel the requests are published on, to declare the exchanges they are routed through.
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// How many calls are waiting for a reply.
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Calls a remote procedure with a request encoded as JSON, and decodes its reply from JSON.
+    ///
+    /// Dropping the returned future cancels the call: a reply arriving later is discarded.
+    pub async fn call<Req, Res>(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        request: &Req,
+        timeout: Duration,
+    ) -> Result<Res, RpcError>
+    where
+        Req: Serialize + ?Sized,
+        Res: DeserializeOwned,
+    {
+        let payload = serde_json::to_vec(request).map_err(RpcError::Serialize)?;
+        let reply = self
+            .call_raw(
+                exchange,
+                routing_key,
+                &payload,
+                Some("application/json"),
+                timeout,
+            )
+            .await?;
+        if reply.kind.as_deref() == Some(ERROR_REPLY) {
+            return Err(remote_error(&reply.payload));
+        }
+        serde_json::from_slice(&reply.payload).map_err(RpcError::Deserialize)
+    }
+
+    /// Calls a remote procedure with a request as is, and returns its reply as is.
+    ///
+    /// Dropping the returned future cancels the call: a reply arriving later is discarded.
+    pub async fn call_raw(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: &[u8],
+        content_type: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Reply, RpcError> {
+        // The call is registered before the request goes out, so that no reply can arrive before it
+        let call = self
+            .pending
+            .register(uuid::Uuid::new_v4().to_string())
+            .ok_or(RpcError::Disconnected)?;
+
+        let mut properties = BasicProperties::default()
+            .with_correlation_id(call.correlation_id().to_string().into())
+            .with_reply_to(self.reply_queue.clone().into());
+        if let Some(content_type) = content_type {
+            properties = properties.with_content_type(content_type.into());
+        }
+
+        // Mandatory has the broker return a request no queue is bound for, instead of silently dropping it
+        let publish = async {
+            let confirm = self
+                .channel
+                .basic_publish(
+                    exchange,
+                    routing_key,
+                    BasicPublishOptions {
+                        mandatory: true,
+                        ..BasicPublishOptions::default()
+                    },
+                    payload,
+                    properties,
+                )
+                .await
+                .map_err(|e| RpcError::Publish(e.to_string()))?;
+            match confirm
+                .await
+                .map_err(|e| RpcError::Publish(e.to_string()))?
+            {
+                Confirmation::Ack(None) | Confirmation::NotRequested => Ok(()),
+                Confirmation::Ack(Some(_)) => Err(RpcError::Unroutable {
+                    exchange: exchange.to_string(),
+                    routing_key: routing_key.to_string(),
+                }),
+                Confirmation::Nack(_) => Err(RpcError::Publish(String::from(
+                    "the broker rejected the request",
+                ))),
+            }
+        };
+
+        await_reply(call, publish, timeout).await
+    }
+}
+
+/// Publishes the request of a call and waits for its reply, all within the timeout of the call.
+///
+/// A broker that holds back the publish or its confirm, such as under flow control, counts against the timeout too.
+/// The call is forgotten once the timeout fires.
+async fn await_reply(
+    mut call: PendingCall,
+    publish: impl Future<Output = Result<(), RpcError>>,
+    timeout: Duration,
+) -> Result<Reply, RpcError> {
+    let exchange = async move {
+        publish.await?;
+        call.reply().await.ok_or(RpcError::Disconnected)
+    };
+    match tokio::time::timeout(timeout, exchange).await {
+        Ok(result) => result,
+        Err(_) => Err(RpcError::Timeout(timeout)),
+    }
+}
+
+/// Reads the error envelope a responder replies with once it gave up on a request.
+fn remote_error(payload: &[u8]) -> RpcError {
+    #[derive(Deserialize)]
+    struct Envelope {
+        error: Remote,
+    }
+    #[derive(Deserialize)]
+    struct Remote {
+        code: String,
+        message: String,
+        #[serde(default)]
+        attempts: u32,
+    }
+
+    match serde_json::from_slice::<Envelope>(payload) {
+        Ok(Envelope { error }) => RpcError::Remote {
+            code: error.code,
+            message: error.message,
+            attempts: error.attempts,
+        },
+        Err(e) => RpcError::Deserialize(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_envelopes_become_remote_errors() {
+        let payload =
+            br#"{"error":{"code":"invalid_request","message":"the request is empty","retryable":false,"attempts":1}}"#;
+        match remote_error(payload) {
+            RpcError::Remote {
+                code,
+                message,
+                attempts,
+            } => {
+                assert_eq!(code, "invalid_request");
+                assert_eq!(message, "the request is empty");
+                assert_eq!(attempts, 1);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(remote_error(b"\"oops\"").reason(), "deserialize");
+    }
+
+    #[tokio::test]
+    async fn timeout_covers_a_stalled_publish() {
+        let pending = Pending::default();
+        let call = pending.register(String::from("a")).unwrap();
+
+        let timeout = Duration::from_millis(20);
+        let result = await_reply(call, std::future::pending(), timeout).await;
+        assert!(matches!(result, Err(RpcError::Timeout(_))));
+        assert_eq!(pending.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn timeout_covers_the_reply() {
+        let pending = Pending::default();
+        let call = pending.register(String::from("a")).unwrap();
+
+        let timeout = Duration::from_millis(20);
+        let result = await_reply(call, async { Ok(()) }, timeout).await;
+        assert!(matches!(result, Err(RpcError::Timeout(_))));
+        assert_eq!(pending.len(), 0);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{fmt, time::Duration};
+
+/// Why a call did not return a reply.
+#[derive(Debug)]
+pub enum RpcError {
+    /// The connection to the broker, or the reply queue, could not be set up.
+    Connection(lapin::Error),
+    /// The request could not be encoded as JSON.
+    Serialize(serde_json::Error),
+    /// The request could not be published, or the broker did not confirm it.
+    Publish(String),
+    /// No queue is bound to the exchange with the routing key of the request.
+    Unroutable {
+        exchange: String,
+        routing_key: String,
+    },
+    /// No reply arrived in time.
+    Timeout(Duration),
+    /// The reply queue was lost, such as when the connection closed, so no reply can arrive anymore.
+    Disconnected,
+    /// The responder gave up on the request, replying with an error envelope.
+    Remote {
+        code: String,
+        message: String,
+        attempts: u32,
+    },
+    /// The reply could not be decoded from JSON into the expected response.
+    Deserialize(serde_json::Error),
+}
+
+impl RpcError {
+    /// A short machine readable identifier of the error.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RpcError::Connection(_) => "connection",
+            RpcError::Serialize(_) => "serialize",
+            RpcError::Publish(_) => "publish",
+            RpcError::Unroutable { .. } => "unroutable",
+            RpcError::Timeout(_) => "timeout",
+            RpcError::Disconnected => "disconnected",
+            RpcError::Remote { .. } => "remote",
+            RpcError::Deserialize(_) => "deserialize",
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Connection(e) => write!(f, "could not connect to RabbitMQ: {}", e),
+            RpcError::Serialize(e) => write!(f, "could not encode the request: {}", e),
+            RpcError::Publish(e) => write!(f, "could not publish the request: {}", e),
+            RpcError::Unroutable {
+                exchange,
+                routing_key,
+            } => write!(
+                f,
+                "no queue is bound to exchange '{}' with routing key '{}'",
+                exchange, routing_key
+            ),
+            RpcError::Timeout(timeout) => write!(f, "no reply arrived within {:?}", timeout),
+            RpcError::Disconnected => {
+                write!(f, "the reply queue was lost, so no reply can arrive")
+            }
+            RpcError::Remote {
+                code,
+                message,
+                attempts,
+            } => write!(
+                f,
+                "the responder gave up after {} attempt(s): {}: {}",
+                attempts, code, message
+            ),
+            RpcError::Deserialize(e) => write!(f, "could not decode the reply: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
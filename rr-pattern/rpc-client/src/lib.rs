@@ -0,0 +1,9 @@
+//! An asynchronous RPC client over RabbitMQ, which correlates every request with its reply.
+//!
+//! A single [`client::RpcClient`] keeps many calls in flight over one connection. Their replies arrive on a single
+//! exclusive reply queue, from which they are handed to the call waiting with the same correlation id.
+
+pub mod client;
+pub mod error;
+mod pending;
+pub use pending::Reply;
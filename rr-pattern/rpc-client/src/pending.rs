@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::oneshot;
+
+/// A reply, as handed to the call waiting for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub payload: Vec<u8>,
+    pub content_type: Option<String>,
+    /// The message type, which is `error` for a reply carrying an error envelope rather than a response.
+    pub kind: Option<String>,
+}
+
+/// The calls waiting for a reply, by correlation id.
+#[derive(Debug, Clone, Default)]
+pub struct Pending {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    calls: HashMap<String, oneshot::Sender<Reply>>,
+    /// Whether the reply consumer is gone, so that no reply can arrive anymore.
+    disconnected: bool,
+}
+
+impl Pending {
+    /// Registers a call, which is forgotten again once the returned [`PendingCall`] is dropped.
+    ///
+    /// Returns `None` once disconnected, so that calls fail right away rather than wait for a reply that cannot arrive.
+    pub fn register(&self, correlation_id: String) -> Option<PendingCall> {
+        let mut state = self.lock();
+        if state.disconnected {
+            return None;
+        }
+        let (sender, receiver) = oneshot::channel();
+        state.calls.insert(correlation_id.clone(), sender);
+        Some(PendingCall {
+            pending: self.clone(),
+            correlation_id,
+            receiver,
+        })
+    }
+
+    /// Hands the reply to the call waiting for it. Returns `false` if no call is waiting, such as when it timed out.
+    pub fn resolve(&self, correlation_id: &str, reply: Reply) -> bool {
+        match self.lock().calls.remove(correlation_id) {
+            Some(sender) => sender.send(reply).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Fails every waiting call and every later one, as no reply can arrive anymore.
+    pub fn disconnect(&self) {
+        let mut state = self.lock();
+        state.disconnected = true;
+        state.calls.clear();
+    }
+
+    /// How many calls are waiting for a reply.
+    pub fn len(&self) -> usize {
+        self.lock().calls.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // A panic while holding the lock leaves the state consistent, as every operation on it is a single call
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A call waiting for its reply. Dropping it, such as when the call is cancelled or times out, forgets the call.
+#[derive(Debug)]
+pub struct PendingCall {
+    pending: Pending,
+    correlation_id: String,
+    receiver: oneshot::Receiver<Reply>,
+}
+
+impl PendingCall {
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+
+    /// Waits for the reply. Returns `None` if the reply queue was lost before it arrived.
+    pub async fn reply(&mut self) -> Option<Reply> {
+        (&mut self.receiver).await.ok()
+    }
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        self.pending.lock().calls.remove(&self.correlation_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(payload: &str) -> Reply {
+        Reply {
+            payload: payload.as_bytes().to_vec(),
+            content_type: None,
+            kind: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_reach_the_call_with_their_correlation_id() {
+        let pending = Pending::default();
+        let mut first = pending.register(String::from("a")).unwrap();
+        let mut second = pending.register(String::from("b")).unwrap();
+
+        assert!(pending.resolve("b", reply("to b")));
+        assert!(pending.resolve("a", reply("to a")));
+        assert!(!pending.resolve("c", reply("to nobody")));
+
+        assert_eq!(first.reply().await, Some(reply("to a")));
+        assert_eq!(second.reply().await, Some(reply("to b")));
+    }
+
+    #[tokio::test]
+    async fn dropped_calls_are_forgotten() {
+        let pending = Pending::default();
+        let call = pending.register(String::from("a")).unwrap();
+        assert_eq!(pending.len(), 1);
+
+        drop(call);
+        assert_eq!(pending.len(), 0);
+        assert!(!pending.resolve("a", reply("late")));
+    }
+
+    #[tokio::test]
+    async fn disconnecting_fails_the_waiting_calls() {
+        let pending = Pending::default();
+        let mut call = pending.register(String::from("a")).unwrap();
+
+        pending.disconnect();
+        assert_eq!(call.reply().await, None);
+        assert!(pending.register(String::from("b")).is_none());
+    }
+}